@@ -0,0 +1,375 @@
+use clap::ValueEnum;
+use url::Url;
+
+use crate::cloner::CloneParams;
+use crate::error::{Error, Result};
+use crate::gitlab::types;
+use crate::{gitea, github, gitlab};
+
+/// A separator of namespace parts in repository names on instances without nested groups
+pub(crate) const PATH_SEPARATOR: &str = ".";
+
+/// A kind of the backup instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    Gitlab,
+    Github,
+    /// Gitea or Forgejo
+    Gitea,
+}
+
+/// What is done with backup projects whose source projects aren't fetched anymore
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PruneMode {
+    Archive,
+    Delete,
+}
+
+/// A repository on the backup instance for pushing a project to
+pub(crate) struct Repo {
+    pub http_url: String,
+    pub ssh_url: String,
+    /// The id of the project on a GitLab backup
+    pub project_id: Option<types::ProjectId>,
+    /// The prefix of container images of the project on a GitLab backup
+    pub registry: Option<String>,
+}
+
+pub(crate) enum Client {
+    Gitlab {
+        client: Box<gitlab::Client>,
+        group: Option<types::Group>,
+    },
+    Github {
+        client: github::Client,
+        org: Option<String>,
+        login: String,
+    },
+    Gitea {
+        client: gitea::Client,
+        org: Option<String>,
+        login: String,
+    },
+}
+
+impl Client {
+    pub async fn new(
+        provider: Provider,
+        token: &str,
+        url: Url,
+        group: &Option<String>,
+        p: &CloneParams,
+    ) -> Result<Self> {
+        match provider {
+            Provider::Gitlab => {
+                let client = gitlab::Client::new(token, url, None, p.disable_sync_date)?
+                    .with_http(p.http.client()?)
+                    .with_retry(p.retry)
+                    .with_limiter(p.rate_limiter.clone())
+                    .detect_version()
+                    .await;
+                client
+                    .check_token("backup", &[("backup projects", gitlab::client::API_SCOPES)])
+                    .await?;
+                let group = if let Some(gr) = group {
+                    Some(client.get_group(gr.clone()).await?)
+                } else {
+                    None
+                };
+                Ok(Client::Gitlab {
+                    client: Box::new(client),
+                    group,
+                })
+            }
+            Provider::Github => {
+                let client = github::Client::new(token, url, p.disable_sync_date)?
+                    .with_http(p.http.client()?)
+                    .with_retry(p.retry)
+                    .with_limiter(p.rate_limiter.clone());
+                let login = client.get_current_user().await?.login;
+                Ok(Client::Github {
+                    client,
+                    org: group.clone(),
+                    login,
+                })
+            }
+            Provider::Gitea => {
+                let client = gitea::Client::new(token, url, p.disable_sync_date)?
+                    .with_http(p.http.client()?)
+                    .with_retry(p.retry)
+                    .with_limiter(p.rate_limiter.clone());
+                let login = client.get_current_user().await?.login;
+                Ok(Client::Gitea {
+                    client,
+                    org: group.clone(),
+                    login,
+                })
+            }
+        }
+    }
+
+    /// The name of the user which owns the token
+    pub async fn username(&self) -> Result<String> {
+        match self {
+            Client::Gitlab { client, .. } => Ok(client.get_current_user().await?.username),
+            Client::Github { login, .. } | Client::Gitea { login, .. } => Ok(login.clone()),
+        }
+    }
+
+    /// Creates (or updates) the backup repository of the project, `path` is
+    /// the project path with namespaces which should be kept on the backup instance
+    pub async fn make_repo(&self, path: Vec<String>, project: &types::Project) -> Result<Repo> {
+        match self {
+            Client::Gitlab { client, group } => {
+                let p = client
+                    .make_project_with_namespace(path, group, project)
+                    .await?;
+                Ok(Repo {
+                    http_url: p.http_url_to_repo,
+                    ssh_url: p.ssh_url_to_repo,
+                    project_id: Some(p.id),
+                    registry: p.container_registry_image_prefix,
+                })
+            }
+            Client::Github { client, org, login } => {
+                let r = client
+                    .make_repo_with_namespace(path, org, login, project)
+                    .await?;
+                Ok(Repo {
+                    http_url: r.clone_url,
+                    ssh_url: r.ssh_url,
+                    project_id: None,
+                    registry: None,
+                })
+            }
+            Client::Gitea { client, org, login } => {
+                let r = client
+                    .make_repo_with_namespace(path, org, login, project)
+                    .await?;
+                Ok(Repo {
+                    http_url: r.clone_url,
+                    ssh_url: r.ssh_url,
+                    project_id: None,
+                    registry: None,
+                })
+            }
+        }
+    }
+
+    /// Archives or deletes the backup project at the path (with namespaces, like in
+    /// [`Client::make_repo`]), nothing is changed with `dry_run`. Returns whether the project
+    /// exists (and isn't archived yet for [`PruneMode::Archive`]), only GitLab is supported
+    pub async fn prune_repo(
+        &self,
+        path: &[String],
+        mode: PruneMode,
+        dry_run: bool,
+    ) -> Result<bool> {
+        let Client::Gitlab { client, group } = self else {
+            return Err(Error::Params(
+                "projects can be pruned only on a GitLab backup".to_string(),
+            ));
+        };
+        let path = match group {
+            Some(gr) => format!("{}/{}", gr.full_path, path.join("/")),
+            None => path.join("/"),
+        };
+        let Some(project) = client.project_exist(path).await? else {
+            return Ok(false);
+        };
+        if mode == PruneMode::Archive && project.archived {
+            return Ok(false);
+        }
+        if !dry_run {
+            match mode {
+                PruneMode::Archive => client.archive_project(project.id).await?,
+                PruneMode::Delete => client.delete_project(project.id).await?,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Applies topics, the default branch and visibility of the project to the backup
+    /// repository, only GitLab is supported
+    pub async fn set_metadata(&self, repo: &Repo, project: &types::Project) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                client.update_project_settings(id, project).await?;
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "project metadata can be applied only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Uploads the avatar of the backup repository, only GitLab is supported
+    pub async fn set_avatar(&self, repo: &Repo, file_name: String, image: Vec<u8>) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                client.set_avatar(id, file_name, image).await?;
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "avatars can be uploaded only to a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Replaces protected branch rules of the backup repository, only GitLab is supported
+    pub async fn set_protected_branches(
+        &self,
+        repo: &Repo,
+        branches: &[types::ProtectedRepoBranch],
+    ) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                for branch in branches {
+                    client.set_protected_branch(id, branch).await?;
+                }
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "protected branches can be applied only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Adds badges missing on the backup repository (with other link or image URLs),
+    /// only GitLab is supported
+    pub async fn set_badges(&self, repo: &Repo, badges: &[types::Badge]) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                let existing = client.get_badges(id).await?;
+                for badge in badges {
+                    if !existing
+                        .iter()
+                        .any(|e| e.link_url == badge.link_url && e.image_url == badge.image_url)
+                    {
+                        client.add_badge(id, badge).await?;
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "badges can be added only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Adds deploy keys missing on the backup repository, only GitLab is supported
+    pub async fn set_deploy_keys(&self, repo: &Repo, keys: &[types::DeployKey]) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                let existing = client.get_deploy_keys(id).await?;
+                for key in keys {
+                    if !existing.iter().any(|e| e.key.trim() == key.key.trim()) {
+                        client.add_deploy_key(id, key).await?;
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "deploy keys can be added only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Adds members to the backup repository by matching usernames, the backup user is skipped.
+    /// Returns usernames which aren't found on the backup, only GitLab is supported
+    pub async fn set_members(&self, repo: &Repo, members: &[types::Member]) -> Result<Vec<String>> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                let current = client.get_current_user().await?.username;
+                let mut missing = vec![];
+                for member in members.iter().filter(|m| m.username != current) {
+                    match client.get_user_by_username(&member.username).await? {
+                        Some(user) => client.set_member(id, user.id, member).await?,
+                        None => missing.push(member.username.clone()),
+                    }
+                }
+                Ok(missing)
+            }
+            _ => Err(Error::Params(
+                "members can be added only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Fetches packages of the backup repository, only GitLab is supported
+    pub async fn get_packages(&self, repo: &Repo) -> Result<Vec<types::Package>> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => client.get_packages(id).await,
+            _ => Err(Error::Params(
+                "packages can be uploaded only to a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Uploads the local file of the package to the backup repository,
+    /// only generic and maven packages on GitLab are supported
+    pub async fn upload_package_file(
+        &self,
+        repo: &Repo,
+        package: &types::Package,
+        file: &types::PackageFile,
+        local: &str,
+    ) -> Result<()> {
+        let url = match (self, repo.project_id, package.package_type.as_str()) {
+            (Client::Gitlab { client, .. }, Some(id), "generic" | "maven") => client
+                .package_file_url(id, package, file)
+                .map(|url| (client, url)),
+            _ => None,
+        };
+        match url {
+            Some((client, url)) => client.upload_package_file(url, local).await,
+            None => Err(Error::Params(format!(
+                "{} packages can't be uploaded to the backup",
+                &package.package_type
+            ))),
+        }
+    }
+
+    /// Creates (or updates) labels and milestones of the backup repository,
+    /// only GitLab is supported
+    pub async fn set_labels(
+        &self,
+        repo: &Repo,
+        labels: &[types::Label],
+        milestones: &[types::Milestone],
+    ) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                for label in labels {
+                    client.set_label(id, label).await?;
+                }
+                for milestone in milestones {
+                    client.set_milestone(id, milestone).await?;
+                }
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "labels and milestones can be recreated only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+
+    /// Creates (or updates) CI/CD variables of the backup repository, only GitLab is supported
+    pub async fn set_ci_variables(
+        &self,
+        repo: &Repo,
+        variables: &[types::ProjectVariable],
+    ) -> Result<()> {
+        match (self, repo.project_id) {
+            (Client::Gitlab { client, .. }, Some(id)) => {
+                for variable in variables {
+                    client.set_variable(id, variable).await?;
+                }
+                Ok(())
+            }
+            _ => Err(Error::Params(
+                "CI/CD variables can be recreated only on a GitLab backup".to_string(),
+            )),
+        }
+    }
+}
@@ -1,82 +1,511 @@
-use clap::Parser;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{IsTerminal, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use crate::cloner::{clone, BackupGitlabOptions, CloneParams, FetchGitlabOptions, FilterPatterns};
-use anyhow::{bail, Result};
+use chrono::{Local, NaiveDate};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use croner::Cron;
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use tokio::sync::mpsc;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{Layer, Registry};
+use url::Url;
+
+use crate::config;
+use crate::events::{self, with_events, Events};
+use crate::listen;
+use crate::logfile::{LogRotation, RotatingFile};
+use crate::tokens;
+use crate::tui::{self, Dashboard, Tui};
+use anyhow::{anyhow, bail, Context, Result};
+use gitlobster::cloner::{
+    self, BackupOptions, BackupProvider, CloneParams, DstRule, FailedProject, FailureKind,
+    FetchGitlabOptions, FilterPatterns, GitBackend, HostKeyChecking, IgnoreRules, Progress,
+    ProgressCallback, PruneMode, RenameRule, Report, DEFAULT_CONCURRENCY_LIMIT,
+    DEFAULT_LIST_CONCURRENCY, MAX_ADAPTIVE_CONCURRENCY,
+};
+use gitlobster::gitlab::oauth::{self, Session};
+use gitlobster::gitlab::{types, Auth};
+use gitlobster::http::HttpOptions;
+use gitlobster::limiter::{parse_bandwidth, parse_size, RateLimiter};
+use gitlobster::restore::{self, RestoreParams, TargetGitlabOptions};
+use gitlobster::retry::{Retry, DEFAULT_RETRIES, DEFAULT_RETRY_DELAY};
+use gitlobster::shutdown::Shutdown;
+use gitlobster::state::STATE_DIR;
+
+/// Alternative env variables for tokens, they are used when neither
+/// the flag nor its GTLBSTR_* env variable is set, but override a config file
+const TOKEN_ENVS: [(&str, &str); 3] = [
+    ("ft", "GITLOBSTER_FETCH_TOKEN"),
+    ("bt", "GITLOBSTER_BACKUP_TOKEN"),
+    ("target_token", "GITLOBSTER_TARGET_TOKEN"),
+];
+
+/// Tokens of the config file by argument ids
+static CONFIG_TOKENS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// The token of the alternative env variable or the config file, it's used when neither
+/// the flag nor its GTLBSTR_* env variable is set; tokens aren't defaults of arguments,
+/// `--help` would print them
+fn fallback_token(id: &str) -> Option<String> {
+    TOKEN_ENVS
+        .iter()
+        .filter(|(token_id, _)| *token_id == id)
+        .find_map(|(_, env)| std::env::var(env).ok().filter(|t| !t.is_empty()))
+        .or_else(|| {
+            CONFIG_TOKENS
+                .get()
+                .and_then(|tokens| tokens.get(id))
+                .cloned()
+        })
+}
+
+/// The `--ft` value which logs in with OAuth instead of a token
+const OAUTH_TOKEN: &str = "oauth";
+/// The `--ft` value which uses the job token of GitLab CI
+const JOB_TOKEN: &str = "job-token";
+const CI_JOB_TOKEN_ENV: &str = "CI_JOB_TOKEN";
+const CI_SERVER_URL_ENV: &str = "CI_SERVER_URL";
+const CI_PROJECT_PATH_ENV: &str = "CI_PROJECT_PATH";
+/// The path of token files to read the token from stdin
+const STDIN_PATH: &str = "-";
+
+/// Flags of the root command which can be used together with a subcommand
+const GLOBAL_ARGS: [&str; 8] = [
+    "config",
+    "verbose",
+    "quiet",
+    "log_format",
+    "log_file",
+    "log_max_size",
+    "log_rotation",
+    "log_keep",
+];
+
+/// Exit codes, so wrappers and monitoring can tell failures apart without parsing the output:
+/// the run failed before it's finished (invalid config, auth or network errors)
+const EXIT_FATAL: u8 = 1;
+/// The run is finished, but some projects failed
+const EXIT_PROJECTS_FAILED: u8 = 2;
+/// The run is interrupted
+const EXIT_INTERRUPTED: u8 = 3;
+
+/// An error of a finished run with failed projects, it has its own exit code
+#[derive(Debug)]
+struct ProjectsFailed(String);
+
+impl std::fmt::Display for ProjectsFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ProjectsFailed {}
+
+pub fn exit_code(e: &anyhow::Error) -> u8 {
+    // a run aborted by --max-failures is finished early
+    match e.downcast_ref::<gitlobster::Error>() {
+        Some(gitlobster::Error::Aborted(_)) => EXIT_PROJECTS_FAILED,
+        Some(gitlobster::Error::Interrupted(_)) => EXIT_INTERRUPTED,
+        _ if e.is::<ProjectsFailed>() => EXIT_PROJECTS_FAILED,
+        _ => EXIT_FATAL,
+    }
+}
+
+/// Usage errors are fatal, the exit code 2 of clap is used for failed projects
+fn exit_usage(e: clap::Error) -> ! {
+    let _ = e.print();
+    std::process::exit(if e.use_stderr() { EXIT_FATAL.into() } else { 0 })
+}
+
+/// The first interrupt (SIGINT or SIGTERM) stops the run after projects in progress,
+/// the next one (or the end of --shutdown-timeout) cancels them
+fn handle_interrupts(shutdown: Arc<Shutdown>, tui: bool) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).ok();
+        loop {
+            let terminated = async {
+                #[cfg(unix)]
+                if let Some(terminate) = &mut terminate {
+                    terminate.recv().await;
+                    return;
+                }
+                std::future::pending::<()>().await
+            };
+            tokio::select! {
+                result = tokio::signal::ctrl_c() => if result.is_err() {
+                    return;
+                },
+                _ = terminated => {}
+            }
+            interrupt(&shutdown, tui);
+        }
+    });
+}
+
+/// The dashboard takes the terminal, so the message goes to its log pane
+pub fn interrupt(shutdown: &Shutdown, tui: bool) {
+    let message = if shutdown.interrupt() {
+        "Interrupted, cancelling projects in progress"
+    } else {
+        "Interrupted, stopping after projects in progress (interrupt again to cancel them)"
+    };
+    if tui {
+        tracing::warn!("{}", message);
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// Interrupted runs can be continued
+fn resume_hint(e: gitlobster::Error) -> anyhow::Error {
+    let interrupted = matches!(e, gitlobster::Error::Interrupted(_));
+    let e = anyhow::Error::from(e);
+    if interrupted {
+        e.context("The run is interrupted, run it with --resume to continue")
+    } else {
+        e
+    }
+}
+
+/// Interrupts kill git processes too, the state has all projects synced so far
+fn exit_on_interrupt() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Interrupted");
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+    });
+}
+
+fn check_failed(report: &Report) -> Result<()> {
+    match report.failed.len() {
+        0 => Ok(()),
+        n => Err(ProjectsFailed(format!("{} projects failed", n)).into()),
+    }
+}
+
+/// A lock file of the daemon in the state directory
+const DAEMON_LOCK: &str = "daemon.lock";
+
+const UPLOAD_ERR: &str =
+    "For upload to another gitlab, you must specify both the --bt and --bu flags";
 
 #[derive(Parser)]
-#[command(author, version, about)]
+#[command(author, version, about, subcommand_negates_reqs = true)]
 /// A tool for cloning all available repositories in a GitLab instance
 struct Cli {
-    /// The GitLab instance URL for fetch repositories (example: https://gitlab.local/)
+    /// A TOML config file with default values of options, keys are option names (default: ./gitlobster.toml)
+    #[arg(long, global = true, env = "GTLBSTR_CONFIG", value_name = "FILE")]
+    config: Option<String>,
+
+    /// Verbose level (one or more, max four)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Print nothing on success, only failed projects and errors to stderr (for cron)
     #[arg(
+        short,
         long,
-        value_parser,
-        env = "GTLBSTR_FETCH_URL",
-        value_name = "FETCH URL"
+        global = true,
+        env = "GTLBSTR_QUIET",
+        conflicts_with = "verbose"
     )]
-    fu: String,
+    quiet: bool,
+
+    /// The format of logs, `json` has fields of events and the project path in `span` (the dashboard shows text logs)
+    #[arg(long, global = true, env = "GTLBSTR_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Also write logs to the file (at least of the -vv level, without colors), it's appended to and rotated by --log-max-size and --log-rotation
+    #[arg(long, global = true, env = "GTLBSTR_LOG_FILE", value_name = "FILE")]
+    log_file: Option<PathBuf>,
 
-    /// Your personal GitLab token for fetch repositories
+    /// Rotate the log file when it would grow over the size (e.g. 10Mi)
+    #[arg(long, global = true, env = "GTLBSTR_LOG_MAX_SIZE", value_parser = parse_size, value_name = "SIZE")]
+    log_max_size: Option<u64>,
+
+    /// Rotate the log file every hour or day, its rotated copies are <FILE>.1 (the newest), <FILE>.2, ...
+    #[arg(long, global = true, env = "GTLBSTR_LOG_ROTATION", value_enum, default_value_t = LogRotation::Never)]
+    log_rotation: LogRotation,
+
+    /// Rotated log files to keep
     #[arg(
         long,
-        value_parser,
-        env = "GTLBSTR_FETCH_TOKEN",
-        value_name = "FETCH TOKEN"
+        global = true,
+        env = "GTLBSTR_LOG_KEEP",
+        default_value_t = 5,
+        value_name = "N"
+    )]
+    log_keep: usize,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    // running without a subcommand works as `clone`, or as `backup` if the backup flags are passed
+    #[command(flatten)]
+    clone: CloneArgs,
+
+    #[command(flatten)]
+    backup: BackupArgs,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Clone projects to a local directory
+    Clone(CloneArgs),
+
+    /// Clone projects to a local directory and push them to a backup GitLab
+    Backup {
+        #[command(flatten)]
+        clone: CloneArgs,
+
+        // boxed, so the variant isn't much larger than the others
+        #[command(flatten)]
+        backup: Box<BackupArgs>,
+    },
+
+    /// Show all projects to download
+    List(FetchArgs),
+
+    /// Check that all projects are cloned to a local directory
+    Verify {
+        #[command(flatten)]
+        fetch: FetchArgs,
+
+        #[command(flatten)]
+        local: LocalArgs,
+    },
+
+    /// Create projects of a local directory on a GitLab instance and push them
+    Restore(RestoreArgs),
+
+    /// Manage tokens stored in the OS keyring, they're used when no token is passed
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommands,
+    },
+
+    /// Keep running and clone projects (or back them up with the backup flags) by a schedule
+    Daemon {
+        #[command(flatten)]
+        daemon: DaemonArgs,
+
+        // flags of runs are parsed again for every run, so token files are read again
+        #[command(flatten)]
+        clone: Box<CloneArgs>,
+
+        #[command(flatten)]
+        backup: Box<BackupArgs>,
+    },
+
+    /// Listen for GitLab webhooks and sync projects of push and tag push events at once
+    Listen {
+        #[command(flatten)]
+        listen: ListenArgs,
+
+        #[command(flatten)]
+        clone: Box<CloneArgs>,
+
+        #[command(flatten)]
+        backup: Box<BackupArgs>,
+    },
+
+    /// Print the completion script of the shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Args)]
+struct DaemonArgs {
+    /// A cron expression of runs in the local time: minute, hour, day of month, month, day of week (e.g. "0 2 * * *")
+    #[arg(long, env = "GTLBSTR_SCHEDULE", value_parser = parse_schedule)]
+    schedule: Cron,
+
+    /// Start a run at once, then follow the schedule
+    #[arg(long, env = "GTLBSTR_RUN_NOW")]
+    run_now: bool,
+
+    /// Write the JSON report of every run to <DIR>/report-<start time>.json
+    #[arg(long, env = "GTLBSTR_REPORTS_DIR", value_name = "DIR")]
+    reports_dir: Option<String>,
+}
+
+#[derive(Args)]
+struct ListenArgs {
+    /// The address to listen for webhooks on
+    #[arg(
+        long,
+        env = "GTLBSTR_LISTEN",
+        value_name = "ADDR",
+        default_value = "127.0.0.1:8080"
     )]
-    ft: String,
+    listen: SocketAddr,
 
-    /// The GitLab instance URL for backup repositories (example: https://backup-gitlab.local/)
+    /// The secret token of webhooks, others are rejected (also read from the file of --webhook-secret-file), it's required for addresses other than loopback ones
     #[arg(
         long,
-        value_parser,
-        env = "GTLBSTR_BACKUP_URL",
-        value_name = "BACKUP URL"
+        env = "GTLBSTR_WEBHOOK_SECRET",
+        value_name = "TOKEN",
+        hide_env_values = true
     )]
-    bu: Option<String>,
+    webhook_secret: Option<String>,
+
+    /// A file with the secret token of webhooks (overrides --webhook-secret)
+    #[arg(long, env = "GTLBSTR_WEBHOOK_SECRET_FILE", value_name = "PATH")]
+    webhook_secret_file: Option<String>,
+}
+
+fn parse_schedule(s: &str) -> std::result::Result<Cron, String> {
+    Cron::new(s).parse().map_err(|e| e.to_string())
+}
+
+#[derive(Subcommand)]
+enum AuthCommands {
+    /// Store the token of an instance in the keyring
+    Login {
+        /// The instance URL (example: https://gitlab.local/)
+        #[arg(long, value_name = "URL")]
+        url: String,
+
+        /// A file with the token, `-` to read it from stdin (by default it's prompted)
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<String>,
+    },
+
+    /// Remove the stored token of an instance from the keyring
+    Logout {
+        /// The instance URL (example: https://gitlab.local/)
+        #[arg(long, value_name = "URL")]
+        url: String,
+    },
+}
 
-    /// Your personal GitLab token for backup repositories
+#[derive(Args)]
+struct FetchArgs {
+    /// The GitLab instance URL for fetch repositories (example: https://gitlab.local/)
     #[arg(
         long,
         value_parser,
-        env = "GTLBSTR_BACKUP_TOKEN",
-        value_name = "BACKUP TOKEN"
+        required = true,
+        env = "GTLBSTR_FETCH_URL",
+        value_name = "FETCH URL"
     )]
-    bt: Option<String>,
+    fu: Option<String>,
 
-    /// A target created group on backup GitLab for push repositories
+    /// Your personal GitLab token for fetch repositories, `oauth` to log in with OAuth or `job-token` to use CI_JOB_TOKEN (also read from GITLOBSTER_FETCH_TOKEN env, in GitLab CI jobs the job token is used by default for the instance of the job, without a token the one stored by `auth login` is used)
     #[arg(
         long,
         value_parser,
-        env = "GTLBSTR_BACKUP_GROUP",
-        value_name = "BACKUP GROUP"
+        env = "GTLBSTR_FETCH_TOKEN",
+        value_name = "FETCH TOKEN",
+        hide_env_values = true
     )]
-    bg: Option<String>,
+    ft: Option<String>,
+
+    /// A file with the fetch token, `-` to read it from stdin (overrides --ft)
+    #[arg(long, env = "GTLBSTR_FETCH_TOKEN_FILE", value_name = "PATH")]
+    ft_file: Option<String>,
+
+    /// The application id of an OAuth application on the fetch instance for `--ft oauth`, the application needs the device authorization grant
+    #[arg(long, env = "GTLBSTR_OAUTH_CLIENT_ID", value_name = "ID")]
+    oauth_client_id: Option<String>,
 
-    /// Include regexp patterns (cannot be used together with --exclude flag, may be repeated)
+    /// A file with stored OAuth refresh tokens [default: ~/.config/gitlobster/oauth.json]
+    #[arg(long, env = "GTLBSTR_OAUTH_TOKENS", value_name = "PATH")]
+    oauth_tokens: Option<PathBuf>,
+
+    /// Include regexp patterns, only matching projects are downloaded (may be repeated)
     #[arg(short = 'i', long, env = "GTLBSTR_INCLUDE", value_name = "PATTERN")]
     include: Option<Vec<String>>,
 
-    /// Comma separated exclude regexp patterns (cannot be used together with --include flag, may be repeated)
+    /// Exclude regexp patterns, matching projects are skipped even if they're included (may be repeated)
     #[arg(short = 'x', long, env = "GTLBSTR_EXCLUDE", value_name = "PATTERN")]
     exclude: Option<Vec<String>>,
 
-    /// A destination local folder for save downloaded repositories
+    /// Include glob patterns of project paths, e.g. `infra/**` or `*/legacy-*` (may be repeated)
+    #[arg(long, env = "GTLBSTR_INCLUDE_GLOB", value_name = "GLOB")]
+    include_glob: Option<Vec<String>>,
+
+    /// Exclude glob patterns of project paths, e.g. `infra/**` or `*/legacy-*` (may be repeated)
+    #[arg(long, env = "GTLBSTR_EXCLUDE_GLOB", value_name = "GLOB")]
+    exclude_glob: Option<Vec<String>>,
+
+    /// A filter file with a glob pattern of excluded projects or groups per line, `!` includes them back and `#` starts comments (like .gitignore), e.g. .gitlobsterignore
+    #[arg(long, env = "GTLBSTR_FILTER_FILE", value_name = "PATH")]
+    filter_file: Option<PathBuf>,
+
+    /// Match include and exclude patterns (and patterns of the filter file) regardless of the case
+    #[arg(long, env = "GTLBSTR_IGNORE_CASE")]
+    ignore_case: bool,
+
+    /// Download only projects with the visibility level (may be repeated or comma separated)
     #[arg(
         long,
-        short,
-        value_parser,
-        env = "GTLBSTR_DST",
-        value_name = "DIRECTORY"
+        value_enum,
+        env = "GTLBSTR_VISIBILITY",
+        value_delimiter = ',',
+        value_name = "LEVEL"
     )]
-    dst: Option<String>,
+    visibility: Vec<types::VisibilityLevel>,
 
-    /// Verbose level (one or more, max four)
-    #[arg(short, long, action = clap::ArgAction::Count)]
-    verbose: u8,
+    /// Skip archived projects
+    #[arg(long, env = "GTLBSTR_SKIP_ARCHIVED")]
+    skip_archived: bool,
 
-    /// Show all projects to download
-    #[arg(long)]
-    dry_run: bool,
+    /// Download only archived projects
+    #[arg(long, env = "GTLBSTR_ONLY_ARCHIVED", conflicts_with = "skip_archived")]
+    only_archived: bool,
+
+    /// Skip forks of other projects (projects are listed with the REST API even with --graphql)
+    #[arg(long, env = "GTLBSTR_NO_FORKS")]
+    no_forks: bool,
+
+    /// Download only forks of other projects (projects are listed with the REST API even with --graphql)
+    #[arg(long, env = "GTLBSTR_ONLY_FORKS", conflicts_with = "no_forks")]
+    only_forks: bool,
+
+    /// Download only projects with activity on or after the date (e.g. 2023-01-01)
+    #[arg(long, env = "GTLBSTR_ACTIVE_SINCE", value_name = "DATE")]
+    active_since: Option<NaiveDate>,
+
+    /// Download only projects without activity on or after the date, e.g. to move dormant projects to cold storage
+    #[arg(long, env = "GTLBSTR_INACTIVE_SINCE", value_name = "DATE")]
+    inactive_since: Option<NaiveDate>,
+
+    /// Download only projects with repositories of at least the size (e.g. 10MiB), sizes are known to members with the Reporter role and admins
+    #[arg(long, env = "GTLBSTR_MIN_SIZE", value_parser = parse_size, value_name = "SIZE")]
+    min_size: Option<u64>,
+
+    /// Download only projects with repositories of at most the size (e.g. 2GiB), sizes are known to members with the Reporter role and admins
+    #[arg(long, env = "GTLBSTR_MAX_SIZE", value_parser = parse_size, value_name = "SIZE")]
+    max_size: Option<u64>,
+
+    /// Download only projects with the topic (may be repeated, projects with any of the topics are downloaded)
+    #[arg(long, env = "GTLBSTR_TOPIC", value_name = "TOPIC")]
+    topic: Vec<String>,
+
+    /// Download only projects with the dominant language (may be repeated or comma separated, e.g. rust,go), languages are fetched for every project
+    #[arg(
+        long,
+        env = "GTLBSTR_LANGUAGE",
+        value_delimiter = ',',
+        value_name = "LANGUAGE"
+    )]
+    language: Vec<String>,
 
     /// Low-level option, how many projects can fetch in one request
     #[arg(
@@ -87,19 +516,26 @@ struct Cli {
     )]
     objects_per_page: Option<u32>,
 
-    /// Maximum projects to download
-    #[arg(long, value_parser, env = "GTLBSTR_LIMIT", value_name = "COUNT")]
-    limit: Option<usize>,
+    /// List projects with keyset pagination, it's much faster on instances with many projects (GitLab 13.0+)
+    #[arg(long, env = "GTLBSTR_KEYSET_PAGINATION")]
+    keyset_pagination: bool,
 
-    /// Limit concurrency download
+    /// List projects with the GraphQL API, a page of projects with their sizes and last activity is one query (GitLab 15.0+). Some fields of projects (e.g. the creator and the fork source) are empty in GraphQL lists
+    #[arg(long, env = "GTLBSTR_GRAPHQL")]
+    graphql: bool,
+
+    /// How many pages of project lists (and other lists) are fetched at once, when the first page shows the number of pages
     #[arg(
         long,
-        value_parser,
-        env = "GTLBSTR_CONCURRENCY_LIMIT",
-        default_value_t = 21,
+        env = "GTLBSTR_LIST_CONCURRENCY",
+        default_value_t = DEFAULT_LIST_CONCURRENCY,
         value_name = "LIMIT"
     )]
-    concurrency_limit: usize,
+    list_concurrency: usize,
+
+    /// Maximum projects to download
+    #[arg(long, value_parser, env = "GTLBSTR_LIMIT", value_name = "COUNT")]
+    limit: Option<usize>,
 
     /// Download projects explicitly owned by user
     #[arg(long, env = "GTLBSTR_ONLY_OWNED")]
@@ -109,89 +545,1994 @@ struct Cli {
     #[arg(long, env = "GTLBSTR_ONLY_MEMBERSHIP")]
     only_membership: bool,
 
-    /// Enable download by ssh instead of http. An authorized ssh key is required
-    #[arg(long, env = "GTLBSTR_DOWNLOAD_SSH")]
-    download_ssh: bool,
+    /// Download only projects of the group (a full path, e.g. dev/backend) and its subgroups
+    #[arg(long, env = "GTLBSTR_GROUP", value_name = "PATH")]
+    group: Option<String>,
 
-    /// Enable upload by ssh instead of http. An authorized ssh key is required
-    #[arg(long, env = "GTLBSTR_UPLOAD_SSH")]
-    upload_ssh: bool,
+    /// Download projects shared with the group from other namespaces too (the default)
+    #[arg(long, env = "GTLBSTR_WITH_SHARED", overrides_with = "no_shared")]
+    with_shared: bool,
+
+    /// Skip projects shared with the group from other namespaces
+    #[arg(long, env = "GTLBSTR_NO_SHARED", overrides_with = "with_shared")]
+    no_shared: bool,
+
+    /// Download only personal projects of the user (by the username), e.g. to archive them when the user leaves
+    #[arg(
+        long,
+        env = "GTLBSTR_USER",
+        value_name = "USERNAME",
+        conflicts_with = "group"
+    )]
+    user: Option<String>,
+
+    /// A file with projects to download instead of listing the instance, a project id or a full path per line (empty lines and lines starting with # are skipped)
+    #[arg(
+        long,
+        env = "GTLBSTR_PROJECTS_FROM",
+        value_name = "PATH",
+        conflicts_with_all = ["group", "user"]
+    )]
+    projects_from: Option<PathBuf>,
+
+    /// Also download personal projects of all users (an admin token is needed), the list of all projects may miss some of them depending on settings
+    #[arg(
+        long,
+        env = "GTLBSTR_ALL_PERSONAL",
+        conflicts_with_all = ["group", "user", "projects_from"]
+    )]
+    all_personal: bool,
+
+    #[command(flatten)]
+    network: NetworkArgs,
+}
+
+#[derive(Args)]
+struct NetworkArgs {
+    /// How many times failed API requests (connection errors, 5xx responses) and git operations are retried
+    #[arg(
+        long,
+        env = "GTLBSTR_RETRIES",
+        default_value_t = DEFAULT_RETRIES,
+        value_name = "COUNT"
+    )]
+    retries: usize,
+
+    /// The delay before the first retry in seconds, it's doubled for every next retry (with a random jitter)
+    #[arg(
+        long,
+        env = "GTLBSTR_RETRY_DELAY",
+        default_value_t = DEFAULT_RETRY_DELAY.as_secs(),
+        value_name = "SECONDS"
+    )]
+    retry_delay: u64,
+
+    /// Limit the rate of API requests to the GitLab instances (all of them together), e.g. 0.5 for a request every 2 seconds
+    #[arg(long, env = "GTLBSTR_MAX_REQUESTS_PER_SECOND", value_name = "RATE")]
+    max_requests_per_second: Option<f64>,
+
+    /// Limit the bandwidth of git transfers over HTTP(S) (all of them together), e.g. 10MiB/s or 500KB/s
+    #[arg(
+        long,
+        env = "GTLBSTR_BANDWIDTH_LIMIT",
+        value_parser = parse_bandwidth,
+        value_name = "BYTES/S"
+    )]
+    bandwidth_limit: Option<u64>,
+
+    /// The timeout of an API request in seconds, including the transfer of its body (e.g. an export archive)
+    #[arg(long, env = "GTLBSTR_API_TIMEOUT", value_name = "SECONDS")]
+    api_timeout: Option<u64>,
+
+    /// The timeout of a git operation (a clone, a fetch or a push of a project) in seconds, a project which doesn't finish in time is marked as failed and the run continues
+    #[arg(long, env = "GTLBSTR_GIT_TIMEOUT", value_name = "SECONDS")]
+    git_timeout: Option<u64>,
+
+    /// Pass tokens to git with a credential helper instead of embedding them into remote URLs, so they aren't saved to .git/config and aren't seen in arguments of git commands
+    #[arg(long, env = "GTLBSTR_GIT_CREDENTIAL_HELPER")]
+    git_credential_helper: bool,
+
+    /// The git executable, e.g. a specific git build (git from PATH by default)
+    #[arg(long, env = "GTLBSTR_GIT_PATH", value_name = "PATH")]
+    git_path: Option<String>,
+
+    /// An argument passed to all git commands before the command name (repeatable), e.g. --git-arg=-c --git-arg=protocol.version=2
+    #[arg(
+        long,
+        env = "GTLBSTR_GIT_ARG",
+        value_name = "ARG",
+        allow_hyphen_values = true
+    )]
+    git_arg: Vec<String>,
+
+    /// The policy of ssh for unknown host keys, so unattended runs don't hang on the prompt (the ssh config by default)
+    #[arg(
+        long,
+        env = "GTLBSTR_SSH_STRICT_HOST_KEY_CHECKING",
+        value_enum,
+        value_name = "POLICY"
+    )]
+    ssh_strict_host_key_checking: Option<HostKeyChecking>,
+
+    /// The known hosts file of ssh (~/.ssh/known_hosts by default)
+    #[arg(long, env = "GTLBSTR_KNOWN_HOSTS", value_name = "FILE")]
+    known_hosts: Option<PathBuf>,
+
+    /// A proxy for API requests and git transfers over HTTP(S): http://, https://, socks5:// or socks5h:// with optional credentials. HTTPS_PROXY, HTTP_PROXY and ALL_PROXY are used without it
+    #[arg(long, env = "GTLBSTR_PROXY", value_name = "URL")]
+    proxy: Option<Url>,
+
+    /// A PEM file with CA certificates to trust in addition to the system ones, e.g. an internal CA of a self-hosted instance. Git uses it instead of its default CA bundle
+    #[arg(long, env = "GTLBSTR_CA_CERT", value_name = "PATH")]
+    ca_cert: Option<String>,
 
-    /// Disable saving the directory hierarchy
+    /// Don't verify TLS certificates of API requests and git transfers (insecure, use it only for testing)
+    #[arg(long, env = "GTLBSTR_INSECURE")]
+    insecure: bool,
+
+    /// A PEM file with the client certificate for TLS client authentication (mTLS) of API requests and git transfers
+    #[arg(
+        long,
+        env = "GTLBSTR_CLIENT_CERT",
+        value_name = "PATH",
+        requires = "client_key"
+    )]
+    client_cert: Option<String>,
+
+    /// A PEM file with the (PKCS#8) private key of the client certificate
+    #[arg(
+        long,
+        env = "GTLBSTR_CLIENT_KEY",
+        value_name = "PATH",
+        requires = "client_cert"
+    )]
+    client_key: Option<String>,
+}
+
+#[derive(Args)]
+struct LocalArgs {
+    /// A destination local folder for save downloaded repositories
+    #[arg(
+        long,
+        short,
+        value_parser,
+        env = "GTLBSTR_DST",
+        value_name = "DIRECTORY"
+    )]
+    dst: Option<String>,
+
+    /// Clone projects matching the glob to another directory, e.g. `critical/**=/ssd/backups` (may be repeated, the first matching rule is applied, other projects are cloned to --dst)
+    #[arg(long, env = "GTLBSTR_DST_MAP", value_name = "GLOB=DIRECTORY")]
+    dst_map: Vec<DstRule>,
+
+    /// Disable saving the group hierarchy in the local directory and on the backup instance
     #[arg(long, env = "GTLBSTR_DISABLE_HIERARCHY")]
     disable_hierarchy: bool,
 
-    /// Clear dst path before cloning
-    #[arg(long, env = "GTLBSTR_CLEAR_DST")]
-    clear_dst: bool,
+    /// Flatten the hierarchy by joining namespaces and project names with the separator (e.g. `__` makes group__subgroup__project) instead of keeping only names, implies --disable-hierarchy
+    #[arg(long, env = "GTLBSTR_HIERARCHY_SEPARATOR", value_name = "SEPARATOR")]
+    hierarchy_separator: Option<String>,
 
-    /// Download only default branch
-    #[arg(long, env = "GTLBSTR_ONLY_MASTER")]
-    only_master: bool,
+    /// Keep the first levels of namespaces as directories and flatten only deeper paths, implies --disable-hierarchy
+    #[arg(long, env = "GTLBSTR_HIERARCHY_DEPTH", value_name = "LEVELS")]
+    hierarchy_depth: Option<usize>,
 
-    /// Disable adding sync dates in project descriptions
-    #[arg(long, env = "GTLBSTR_DISABLE_SYNC_DATE")]
-    disable_sync_date: bool,
+    /// Clone bare mirrors (<dst>/<path>.git) without working trees
+    #[arg(long, visible_alias = "bare", env = "GTLBSTR_MIRROR")]
+    mirror: bool,
 }
 
-pub fn run() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Args)]
+struct CloneArgs {
+    #[command(flatten)]
+    fetch: FetchArgs,
 
-    let log_level = match cli.verbose {
-        0 => tracing::Level::ERROR,
-        1 => tracing::Level::WARN,
-        2 => tracing::Level::INFO,
-        3 => tracing::Level::DEBUG,
-        _ => tracing::Level::TRACE,
-    };
-    tracing_subscriber::fmt().with_max_level(log_level).init();
+    #[command(flatten)]
+    local: LocalArgs,
 
-    let fetch_gl = FetchGitlabOptions::new(cli.fu, cli.ft)?;
+    /// Show all projects to download
+    #[arg(long)]
+    dry_run: bool,
 
-    let patterns = if cli.exclude.is_some() && cli.include.is_some() {
-        bail!("You cannot use the --include and --exclude flag together");
-    } else if let Some(patterns) = cli.exclude {
-        Some(FilterPatterns::Exclude(patterns))
-    } else {
-        cli.include.map(FilterPatterns::Include)
-    };
+    /// Show a live dashboard of the run in the terminal: projects in progress, the queue, throughput, failures and recent log lines
+    #[arg(long, env = "GTLBSTR_TUI")]
+    tui: bool,
 
-    let upl_err = "For upload to another gitlab, you must specify both the --bt and --bu flags";
-    let backup_gl = if let (Some(url), Some(token)) = (&cli.bu, &cli.bt) {
-        Some(BackupGitlabOptions::new(
-            url.clone(),
-            token.clone(),
-            cli.bg.clone(),
-        )?)
-    } else {
-        if cli.bu.is_some() || cli.bt.is_some() {
-            bail!(upl_err);
-        };
-        None
-    };
+    /// Write a JSON object per line for every transition of the run (started, queued, fetching, pushing, synced, skipped, failed, pruned, finished) to the file, or to stdout without a value or with `-`
+    #[arg(
+        long,
+        env = "GTLBSTR_EVENTS_JSONL",
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = events::STDOUT
+    )]
+    events_jsonl: Option<String>,
 
-    if backup_gl.is_none() && cli.bg.is_some() {
-        bail!(upl_err);
-    }
-
-    let clone_params = CloneParams {
-        fetch: fetch_gl,
-        dst: cli.dst,
-        backup: backup_gl,
-        patterns,
-        dry_run: cli.dry_run,
-        objects_per_page: cli.objects_per_page,
-        limit: cli.limit,
-        concurrency_limit: cli.concurrency_limit,
-        only_owned: cli.only_owned,
-        only_membership: cli.only_membership,
-        download_ssh: cli.download_ssh,
-        upload_ssh: cli.upload_ssh,
-        disable_hierarchy: cli.disable_hierarchy,
-        clear_dst: cli.clear_dst,
-        only_master: cli.only_master,
-        disable_sync_date: cli.disable_sync_date,
-    };
+    /// Limit concurrency download, `auto` starts low and adjusts it by the latency of API requests, rate limited requests and failures
+    #[arg(
+        long,
+        env = "GTLBSTR_CONCURRENCY_LIMIT",
+        default_value_t = ConcurrencyLimit::Fixed(DEFAULT_CONCURRENCY_LIMIT),
+        value_name = "LIMIT"
+    )]
+    concurrency_limit: ConcurrencyLimit,
+
+    /// Limit concurrency download, it takes precedence over --concurrency-limit
+    #[arg(
+        long,
+        env = "GTLBSTR_DOWNLOAD_CONCURRENCY",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        value_name = "LIMIT"
+    )]
+    download_concurrency: Option<usize>,
+
+    /// Continue the run when projects fail, it's the default: failed projects are reported and retried by the next run
+    #[arg(
+        long,
+        env = "GTLBSTR_CONTINUE_ON_ERROR",
+        overrides_with_all = ["fail_fast", "max_failures"]
+    )]
+    continue_on_error: bool,
 
-    clone(clone_params)
+    /// Abort the run on the first failed project (--max-failures 1)
+    #[arg(
+        long,
+        env = "GTLBSTR_FAIL_FAST",
+        overrides_with_all = ["continue_on_error", "max_failures"]
+    )]
+    fail_fast: bool,
+
+    /// Abort the run when N projects failed, e.g. to find an expired token early in a long run
+    #[arg(
+        long,
+        env = "GTLBSTR_MAX_FAILURES",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        overrides_with_all = ["continue_on_error", "fail_fast"],
+        value_name = "N"
+    )]
+    max_failures: Option<usize>,
+
+    /// Enable download by ssh instead of http. An authorized ssh key is required
+    #[arg(long, env = "GTLBSTR_DOWNLOAD_SSH")]
+    download_ssh: bool,
+
+    /// The private ssh key for downloads, other keys of the agent and `~/.ssh/config` aren't tried
+    #[arg(long, env = "GTLBSTR_DOWNLOAD_SSH_KEY", value_name = "PATH")]
+    download_ssh_key: Option<PathBuf>,
+
+    /// The port of the ssh server for downloads
+    #[arg(long, env = "GTLBSTR_DOWNLOAD_SSH_PORT", value_name = "PORT")]
+    download_ssh_port: Option<u16>,
+
+    /// An option of ssh for downloads (`-o`), e.g. `ConnectTimeout=10`. Can be repeated
+    #[arg(long, env = "GTLBSTR_DOWNLOAD_SSH_OPTION", value_name = "OPTION")]
+    download_ssh_option: Vec<String>,
+
+    /// Clear dst path before cloning
+    #[arg(long, env = "GTLBSTR_CLEAR_DST")]
+    clear_dst: bool,
+
+    /// Download only default branch
+    #[arg(long, env = "GTLBSTR_ONLY_MASTER", conflicts_with = "mirror")]
+    only_master: bool,
+
+    /// Clone and fetch only the last commits of the history (shallow clones can't be pushed to a backup)
+    #[arg(long, env = "GTLBSTR_DEPTH", value_name = "COMMITS")]
+    depth: Option<u32>,
+
+    /// Clone and fetch only the history after the date, e.g. 2023-01-01 (shallow clones can't be pushed to a backup)
+    #[arg(long, env = "GTLBSTR_SINCE", value_name = "DATE")]
+    since: Option<NaiveDate>,
+
+    /// Make partial clones without objects excluded by the filter, e.g. blob:none or tree:0 (partial clones can't be pushed to a backup)
+    #[arg(long, env = "GTLBSTR_FILTER", value_name = "FILTER")]
+    filter: Option<String>,
+
+    /// Clone, fetch and push only branches matching the globs, e.g. main,release/*
+    #[arg(
+        long,
+        env = "GTLBSTR_BRANCHES",
+        value_name = "PATTERNS",
+        value_delimiter = ',',
+        conflicts_with = "only_master"
+    )]
+    branches: Vec<String>,
+
+    /// Also fetch hidden GitLab refs (refs/merge-requests/*, refs/pipelines/*, refs/keep-around/*), they're pushed to the backup under refs/gitlab/
+    #[arg(long, env = "GTLBSTR_HIDDEN_REFS")]
+    hidden_refs: bool,
+
+    /// Run git gc on each repository after fetching it, keeps long-lived clones compact
+    #[arg(long, env = "GTLBSTR_GC")]
+    gc: bool,
+
+    /// Run git gc --aggressive on each repository after fetching it (implies --gc), it's much slower
+    #[arg(long, env = "GTLBSTR_GC_AGGRESSIVE")]
+    gc_aggressive: bool,
+
+    /// Clone forks after their upstream projects and borrow objects of the upstream clones with git alternates to save disk space
+    #[arg(long, env = "GTLBSTR_DEDUPE_FORKS")]
+    dedupe_forks: bool,
+
+    /// An implementation of git fetching repositories: git or gitoxide (only bare mirrors without fetch options, the gitoxide feature is required)
+    #[arg(long, env = "GTLBSTR_GIT_BACKEND", value_enum, default_value_t = GitBackend::Git)]
+    git_backend: GitBackend,
+
+    /// A directory for writing a git bundle of each cloned project (<dir>/<path>.bundle)
+    #[arg(long, env = "GTLBSTR_BUNDLE_DIR", value_name = "DIRECTORY")]
+    bundle_dir: Option<String>,
+
+    /// Write bundles only with changes since the previous run (<dir>/<path>.<timestamp>.bundle)
+    #[arg(long, env = "GTLBSTR_INCREMENTAL_BUNDLES", requires = "bundle_dir")]
+    incremental_bundles: bool,
+
+    /// Skip projects unchanged (by last activity) since the last successful sync (<dst>/.gitlobster/state.db)
+    #[arg(long, env = "GTLBSTR_INCREMENTAL", conflicts_with = "clear_dst")]
+    incremental: bool,
+
+    /// How long projects in progress may finish after an interrupt (SIGINT or SIGTERM) before they're cancelled, the next interrupt cancels them at once
+    #[arg(
+        long,
+        env = "GTLBSTR_SHUTDOWN_TIMEOUT",
+        default_value_t = 60,
+        value_name = "SECONDS"
+    )]
+    shutdown_timeout: u64,
+
+    /// Continue the interrupted run from its incomplete projects instead of fetching and cloning all projects again
+    #[arg(long, env = "GTLBSTR_RESUME", conflicts_with = "clear_dst")]
+    resume: bool,
+
+    /// Remove local directories of projects which aren't fetched anymore (deleted or moved out of scope) after a successful run
+    #[arg(long, env = "GTLBSTR_PRUNE_LOCAL")]
+    prune_local: bool,
+
+    /// Move pruned directories to the <dst>/.trash/ quarantine instead of removing them
+    #[arg(long, env = "GTLBSTR_PRUNE_TO_TRASH", requires = "prune_local")]
+    prune_to_trash: bool,
+
+    /// Only print local directories and backup projects which would be pruned
+    #[arg(long, env = "GTLBSTR_PRUNE_DRY_RUN")]
+    prune_dry_run: bool,
+
+    /// Prune even if no projects are fetched or more than half of known projects would be pruned, such runs are refused as the listing may be incomplete
+    #[arg(long, env = "GTLBSTR_FORCE_PRUNE")]
+    force_prune: bool,
+
+    /// Write a JSON report of the run to the file: projects which are new, updated, failed and disappeared since the previous run, totals, and the status, sizes, duration, error and ref tips of every project (--report-json is an alias)
+    #[arg(
+        long,
+        alias = "report-json",
+        env = "GTLBSTR_REPORT_FILE",
+        value_name = "FILE"
+    )]
+    report_file: Option<String>,
+
+    /// Download GitLab export archives with issues, merge requests and settings (<dst>/<path>.tar.gz)
+    #[arg(long, env = "GTLBSTR_EXPORT_ARCHIVE")]
+    export_archive: bool,
+
+    /// Clone project wikis (<dst>/<path>.wiki) and push them to wikis of backup repositories
+    #[arg(long, env = "GTLBSTR_WIKIS")]
+    wikis: bool,
+
+    /// Clone project snippets (<dst>/<path>.snippets/<id>)
+    #[arg(long, env = "GTLBSTR_SNIPPETS")]
+    snippets: bool,
+
+    /// Clone personal snippets of the user (<dst>/<username>.snippets/<id>)
+    #[arg(long, env = "GTLBSTR_PERSONAL_SNIPPETS")]
+    personal_snippets: bool,
+
+    /// Clone design management repositories (<dst>/<path>.design), if they exist
+    #[arg(long, env = "GTLBSTR_DESIGNS")]
+    designs: bool,
+
+    /// Fetch LFS objects of all refs and push them to the backup, git-lfs is required
+    #[arg(long, env = "GTLBSTR_LFS")]
+    lfs: bool,
+
+    /// Export issues with notes to JSON (<dst>/<path>.issues.json)
+    #[arg(long, env = "GTLBSTR_WITH_ISSUES")]
+    with_issues: bool,
+
+    /// Export merge requests with diff refs, approvals and discussions to JSON (<dst>/<path>.merge_requests.json)
+    #[arg(long, env = "GTLBSTR_WITH_MERGE_REQUESTS")]
+    with_merge_requests: bool,
+
+    /// Copy container images to registries of backup projects (only GitLab), or save them as OCI archives (<dst>/<path>.registry/) without a GitLab backup, skopeo is required
+    #[arg(long, env = "GTLBSTR_WITH_REGISTRY")]
+    with_registry: bool,
+
+    /// Download generic, maven, npm and pypi packages of the package registry (<dst>/<path>.packages/)
+    #[arg(long, env = "GTLBSTR_WITH_PACKAGES")]
+    with_packages: bool,
+
+    /// Download releases with their linked assets (<dst>/<path>.releases/), interrupted downloads are resumed
+    #[arg(long, env = "GTLBSTR_WITH_RELEASES")]
+    with_releases: bool,
+
+    /// Export webhooks and integrations settings (URLs, events, properties) to JSON (<dst>/<path>.hooks.json), secret fields are removed
+    #[arg(long, env = "GTLBSTR_WITH_HOOKS")]
+    with_hooks: bool,
+
+    /// Keep secret fields (tokens, passwords, keys) of webhooks and integrations returned by the API
+    #[arg(long, env = "GTLBSTR_INCLUDE_HOOK_SECRETS", requires = "with_hooks")]
+    include_hook_secrets: bool,
+
+    /// Export badges of projects and their groups to JSON (<dst>/<path>.badges.json)
+    #[arg(long, env = "GTLBSTR_WITH_BADGES")]
+    with_badges: bool,
+
+    /// Export public deploy keys and deploy tokens metadata (names, scopes) to JSON (<dst>/<path>.deploy_keys.json)
+    #[arg(long, env = "GTLBSTR_WITH_DEPLOY_KEYS")]
+    with_deploy_keys: bool,
+
+    /// Export members of projects and their groups with access levels and expiry dates to JSON (<dst>/<path>.members.json)
+    #[arg(long, env = "GTLBSTR_WITH_MEMBERS")]
+    with_members: bool,
+
+    /// Export labels and milestones of projects and their groups to JSON (<dst>/<path>.labels.json, <dst>/<path>.milestones.json)
+    #[arg(long, env = "GTLBSTR_WITH_LABELS")]
+    with_labels: bool,
+
+    /// Export CI/CD variables to JSON (<dst>/<path>.ci_variables.json)
+    #[arg(long, env = "GTLBSTR_WITH_CI_VARIABLES")]
+    with_ci_variables: bool,
+
+    /// Export values of masked CI/CD variables too, they are skipped by default
+    #[arg(long, env = "GTLBSTR_INCLUDE_MASKED_VARIABLES")]
+    include_masked_variables: bool,
+
+    /// Encrypt exported CI/CD variables with the passphrase (<dst>/<path>.ci_variables.json.age, decrypted by `age -d`)
+    #[arg(
+        long,
+        env = "GTLBSTR_CI_VARIABLES_PASSPHRASE",
+        value_name = "PASSPHRASE",
+        hide_env_values = true
+    )]
+    ci_variables_passphrase: Option<String>,
+
+    /// Save project settings to JSON (<dst>/<path>.project.json)
+    #[arg(long, env = "GTLBSTR_WITH_METADATA")]
+    with_metadata: bool,
+}
+
+#[derive(Args)]
+struct BackupArgs {
+    /// The GitLab (GitHub, Gitea) instance URL for backup repositories (example: https://backup-gitlab.local/)
+    #[arg(
+        long,
+        value_parser,
+        env = "GTLBSTR_BACKUP_URL",
+        value_name = "BACKUP URL"
+    )]
+    bu: Option<String>,
+
+    /// Your personal GitLab token for backup repositories (also read from GITLOBSTER_BACKUP_TOKEN env, without a token the one stored by `auth login` is used)
+    #[arg(
+        long,
+        value_parser,
+        env = "GTLBSTR_BACKUP_TOKEN",
+        value_name = "BACKUP TOKEN",
+        hide_env_values = true
+    )]
+    bt: Option<String>,
+
+    /// A file with the backup token, `-` to read it from stdin (overrides --bt)
+    #[arg(long, env = "GTLBSTR_BACKUP_TOKEN_FILE", value_name = "PATH")]
+    bt_file: Option<String>,
+
+    /// A target created group on backup GitLab (or an organization on GitHub, Gitea) for push repositories
+    #[arg(
+        long,
+        value_parser,
+        env = "GTLBSTR_BACKUP_GROUP",
+        value_name = "BACKUP GROUP"
+    )]
+    bg: Option<String>,
+
+    /// A kind of the backup instance, on GitHub and Gitea the group hierarchy is kept in repository names (group.subgroup.project)
+    #[arg(
+        long,
+        value_enum,
+        env = "GTLBSTR_BACKUP_PROVIDER",
+        default_value_t = BackupProvider::Gitlab,
+        value_name = "PROVIDER"
+    )]
+    backup_provider: BackupProvider,
+
+    /// Enable upload by ssh instead of http. An authorized ssh key is required
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH")]
+    upload_ssh: bool,
+
+    /// The private ssh key for uploads, other keys of the agent and `~/.ssh/config` aren't tried
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH_KEY", value_name = "PATH")]
+    upload_ssh_key: Option<PathBuf>,
+
+    /// The port of the ssh server for uploads
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH_PORT", value_name = "PORT")]
+    upload_ssh_port: Option<u16>,
+
+    /// An option of ssh for uploads (`-o`), e.g. `ConnectTimeout=10`. Can be repeated
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH_OPTION", value_name = "OPTION")]
+    upload_ssh_option: Vec<String>,
+
+    /// Limit concurrency upload to the backup, e.g. when the backup handles fewer parallel pushes than the source (--concurrency-limit by default)
+    #[arg(
+        long,
+        env = "GTLBSTR_UPLOAD_CONCURRENCY",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        value_name = "LIMIT"
+    )]
+    upload_concurrency: Option<usize>,
+
+    /// Disable adding sync dates in project descriptions
+    #[arg(long, env = "GTLBSTR_DISABLE_SYNC_DATE")]
+    disable_sync_date: bool,
+
+    /// Archive (or delete) backup projects whose source projects aren't fetched anymore after a successful run (only GitLab is supported)
+    #[arg(
+        long,
+        value_enum,
+        env = "GTLBSTR_PRUNE_BACKUP",
+        num_args = 0..=1,
+        default_missing_value = "archive",
+        value_name = "MODE"
+    )]
+    prune_backup: Option<PruneMode>,
+
+    /// Rename backup projects: a regexp matching the start of source project paths and its replacement, e.g. `old-group/=new-group/` (captures are referenced as $1, may be repeated, the first matching rule is applied)
+    #[arg(long, env = "GTLBSTR_RENAME", value_name = "FROM=TO")]
+    rename: Vec<RenameRule>,
+
+    /// Add exported badges to backup projects, URLs of the fetched GitLab are replaced with the backup URL (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_BADGES", requires = "with_badges")]
+    sync_badges: bool,
+
+    /// Add exported deploy keys to backup projects (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_DEPLOY_KEYS", requires = "with_deploy_keys")]
+    sync_deploy_keys: bool,
+
+    /// Add exported direct members of projects to backup projects by matching usernames (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_MEMBERS", requires = "with_members")]
+    sync_members: bool,
+
+    /// Upload downloaded generic and maven packages to backup projects (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_PACKAGES", requires = "with_packages")]
+    sync_packages: bool,
+
+    /// Create exported labels and milestones on backup projects (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_LABELS", requires = "with_labels")]
+    sync_labels: bool,
+
+    /// Create exported CI/CD variables on backup projects (only GitLab is supported)
+    #[arg(
+        long,
+        env = "GTLBSTR_SYNC_CI_VARIABLES",
+        requires = "with_ci_variables"
+    )]
+    sync_ci_variables: bool,
+
+    /// Apply topics, the default branch and visibility of projects to backup projects (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_METADATA")]
+    sync_metadata: bool,
+
+    /// Upload avatars of projects to backup projects (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_AVATARS")]
+    sync_avatars: bool,
+
+    /// Apply protected branch rules of projects (push, merge and force push levels of roles) to backup projects (only GitLab is supported)
+    #[arg(long, env = "GTLBSTR_SYNC_PROTECTED_BRANCHES")]
+    sync_protected_branches: bool,
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// The GitLab instance URL for restore repositories (example: https://new-gitlab.local/)
+    #[arg(
+        long,
+        value_parser,
+        required = true,
+        env = "GTLBSTR_TARGET_URL",
+        value_name = "TARGET URL"
+    )]
+    target_url: Option<String>,
+
+    /// Your personal GitLab token for restore repositories (also read from GITLOBSTER_TARGET_TOKEN env, without a token the one stored by `auth login` is used)
+    #[arg(
+        long,
+        value_parser,
+        env = "GTLBSTR_TARGET_TOKEN",
+        value_name = "TARGET TOKEN",
+        hide_env_values = true
+    )]
+    target_token: Option<String>,
+
+    /// A file with the target token, `-` to read it from stdin (overrides --target-token)
+    #[arg(long, env = "GTLBSTR_TARGET_TOKEN_FILE", value_name = "PATH")]
+    target_token_file: Option<String>,
+
+    /// A created group on the target GitLab for restore projects to (by default projects are restored to their original paths)
+    #[arg(
+        long,
+        value_parser,
+        env = "GTLBSTR_TARGET_GROUP",
+        value_name = "TARGET GROUP"
+    )]
+    target_group: Option<String>,
+
+    /// A local folder with cloned repositories (or export archives)
+    #[arg(
+        long,
+        short,
+        value_parser,
+        required = true,
+        env = "GTLBSTR_SRC",
+        value_name = "DIRECTORY"
+    )]
+    src: Option<String>,
+
+    /// Import export archives (<src>/<path>.tar.gz) instead of pushing repositories
+    #[arg(long, env = "GTLBSTR_ARCHIVES")]
+    archives: bool,
+
+    /// Create labels and milestones exported by --with-labels on restored projects
+    #[arg(long, env = "GTLBSTR_WITH_LABELS")]
+    with_labels: bool,
+
+    /// Show all projects to restore
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Limit concurrency upload
+    #[arg(
+        long,
+        value_parser,
+        env = "GTLBSTR_CONCURRENCY_LIMIT",
+        default_value_t = DEFAULT_CONCURRENCY_LIMIT,
+        value_name = "LIMIT"
+    )]
+    concurrency_limit: usize,
+
+    /// Enable upload by ssh instead of http. An authorized ssh key is required
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH")]
+    upload_ssh: bool,
+
+    /// The private ssh key for uploads, other keys of the agent and `~/.ssh/config` aren't tried
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH_KEY", value_name = "PATH")]
+    upload_ssh_key: Option<PathBuf>,
+
+    /// The port of the ssh server for uploads
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH_PORT", value_name = "PORT")]
+    upload_ssh_port: Option<u16>,
+
+    /// An option of ssh for uploads (`-o`), e.g. `ConnectTimeout=10`. Can be repeated
+    #[arg(long, env = "GTLBSTR_UPLOAD_SSH_OPTION", value_name = "OPTION")]
+    upload_ssh_option: Vec<String>,
+
+    #[command(flatten)]
+    network: NetworkArgs,
+}
+
+/// How log lines are formatted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    #[default]
+    Text,
+    /// A JSON object per line, for log collectors like Loki or ELK
+    Json,
+}
+
+/// A fixed concurrency, or `auto` to adjust it by the load of instances
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConcurrencyLimit {
+    Fixed(usize),
+    Auto,
+}
+
+impl std::str::FromStr for ConcurrencyLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ConcurrencyLimit::Auto),
+            _ => match s.parse::<usize>() {
+                Ok(limit) if limit > 0 => Ok(ConcurrencyLimit::Fixed(limit)),
+                _ => Err(format!("{} isn't a positive number or auto", s)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ConcurrencyLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConcurrencyLimit::Fixed(limit) => write!(f, "{}", limit),
+            ConcurrencyLimit::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// The absolute path of the ssh key, git commands run in directories of repositories
+fn ssh_key(key: Option<PathBuf>) -> Result<Option<String>> {
+    key.map(|k| {
+        std::fs::canonicalize(&k)
+            .with_context(|| format!("Invalid ssh key {}", k.display()))
+            .map(|k| k.to_string_lossy().to_string())
+    })
+    .transpose()
+}
+
+impl NetworkArgs {
+    /// The absolute path of the known hosts file, ssh runs in directories of repositories
+    fn known_hosts(&self) -> Result<Option<String>> {
+        self.known_hosts
+            .as_ref()
+            .map(|k| {
+                std::path::absolute(k)
+                    .with_context(|| format!("Invalid known hosts file {}", k.display()))
+                    .map(|k| k.to_string_lossy().to_string())
+            })
+            .transpose()
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            delay: Duration::from_secs(self.retry_delay),
+        }
+    }
+
+    fn http(&self) -> HttpOptions {
+        HttpOptions {
+            timeout: self.api_timeout.map(Duration::from_secs),
+            proxy: self.proxy.clone(),
+            ca_cert: self.ca_cert.clone(),
+            insecure: self.insecure,
+            client_cert: self.client_cert.clone().zip(self.client_key.clone()),
+        }
+    }
+
+    fn rate_limiter(&self) -> Result<Option<Arc<RateLimiter>>> {
+        match self.max_requests_per_second {
+            Some(rate) if rate <= 0.0 || !rate.is_finite() => {
+                bail!("The --max-requests-per-second value must be positive")
+            }
+            Some(rate) => Ok(Some(Arc::new(RateLimiter::new(rate)))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads the token from the file or from stdin for `-`, surrounding whitespace is trimmed
+fn read_token(path: &str) -> Result<String> {
+    let token = if path == STDIN_PATH {
+        static STDIN_READ: AtomicBool = AtomicBool::new(false);
+        if STDIN_READ.swap(true, Ordering::Relaxed) {
+            bail!("Only one token can be read from stdin");
+        }
+        let mut token = String::new();
+        std::io::stdin()
+            .read_to_string(&mut token)
+            .context("Failed to read the token from stdin")?;
+        token
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the token from {}", path))?
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("The token in {} is empty", path);
+    }
+    Ok(token.to_string())
+}
+
+/// Adds glob patterns to regexp ones
+fn with_globs(patterns: Option<Vec<String>>, globs: Option<Vec<String>>) -> Option<Vec<String>> {
+    let globs = globs.map(|globs| globs.iter().map(|g| cloner::glob_to_regex(g)).collect());
+    match (patterns, globs) {
+        (Some(mut patterns), Some(globs)) => {
+            patterns.extend(globs);
+            Some(patterns)
+        }
+        (patterns, globs) => patterns.or(globs),
+    }
+}
+
+/// Checks that the options are supported by the gitoxide backend
+fn check_gitoxide(p: &CloneParams) -> Result<()> {
+    if !cfg!(feature = "gitoxide") {
+        bail!("gitlobster is built without the gitoxide feature, --git-backend=gitoxide isn't available");
+    }
+    if !p.mirror {
+        bail!("--git-backend=gitoxide clones only bare mirrors, use it with --mirror");
+    }
+    let o = &p.fetch_options;
+    if o.is_shallow() || o.filter.is_some() || !o.branches.is_empty() || o.hidden_refs {
+        bail!("--depth, --since, --filter, --branches and --hidden-refs aren't supported by --git-backend=gitoxide");
+    }
+    if p.dedupe_forks || p.git_credential_helper {
+        bail!(
+            "--dedupe-forks and --git-credential-helper aren't supported by --git-backend=gitoxide"
+        );
+    }
+    let http = &p.http;
+    if http.proxy.is_some()
+        || http.ca_cert.is_some()
+        || http.insecure
+        || http.client_cert.is_some()
+        || p.bandwidth_limit.is_some()
+    {
+        bail!("--proxy, --ca-cert, --insecure, --client-cert and --bandwidth-limit aren't supported by --git-backend=gitoxide");
+    }
+    let ssh = &p.download_ssh_options;
+    if p.download_ssh
+        && (ssh.key.is_some()
+            || ssh.port.is_some()
+            || !ssh.options.is_empty()
+            || p.ssh_host_key_checking.is_some()
+            || p.known_hosts.is_some())
+    {
+        bail!("--download-ssh-*, --ssh-strict-host-key-checking and --known-hosts aren't supported by --git-backend=gitoxide");
+    }
+    Ok(())
+}
+
+/// Whether the URL is of the instance running the CI job, the job token is sent only to it
+fn ci_instance(url: &Url) -> bool {
+    std::env::var(CI_SERVER_URL_ENV)
+        .ok()
+        .and_then(|ci| Url::parse(&ci).ok())
+        .is_some_and(|ci| ci.origin() == url.origin())
+}
+
+/// Reads project ids or paths from the file, a project per line
+fn read_projects(path: &PathBuf) -> Result<Vec<String>> {
+    let projects: Vec<String> = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read projects from {}", path.display()))?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+    if projects.is_empty() {
+        bail!("No projects in {}", path.display());
+    }
+    Ok(projects)
+}
+
+impl FetchArgs {
+    async fn into_params(self) -> Result<CloneParams> {
+        let url = self
+            .fu
+            .ok_or_else(|| anyhow!("The --fu flag is required"))?;
+        let parsed = Url::parse(&url)?;
+        // a job of GitLab CI backs up its instance with the job token by default
+        let ci_job = std::env::var_os(CI_JOB_TOKEN_ENV).is_some() && ci_instance(&parsed);
+        let token = match (self.ft_file, self.ft) {
+            (Some(path), _) => read_token(&path)?,
+            (None, Some(token)) => token,
+            (None, None) => fallback_token("ft")
+                .or_else(|| ci_job.then(|| JOB_TOKEN.to_string()))
+                .or_else(|| tokens::find(&url))
+                .ok_or_else(|| {
+                    anyhow!("The --ft flag is required, or store the token with `auth login`")
+                })?,
+        };
+        if token == JOB_TOKEN && !ci_instance(&parsed) {
+            bail!(
+                "The job token is sent only to the instance of the CI job ({}), the --ft flag is required for {}",
+                CI_SERVER_URL_ENV,
+                url
+            );
+        }
+        let mut fetch = FetchGitlabOptions::new(url, token.clone())?;
+        if token == OAUTH_TOKEN {
+            let client_id = self
+                .oauth_client_id
+                .ok_or_else(|| anyhow!("The --oauth-client-id flag is required for OAuth"))?;
+            let session = Session::login(
+                self.network.http().client()?,
+                fetch.url().clone(),
+                client_id,
+                self.oauth_tokens.or_else(oauth::default_store),
+                |code| {
+                    eprintln!(
+                        "To log in to {}, open {} and enter the code {}",
+                        fetch.url(),
+                        code.verification_uri_complete
+                            .as_ref()
+                            .unwrap_or(&code.verification_uri),
+                        code.user_code
+                    )
+                },
+            )
+            .await?;
+            fetch = fetch.with_auth(Auth::OAuth(session));
+        } else if token == JOB_TOKEN {
+            let job_token = std::env::var(CI_JOB_TOKEN_ENV).map_err(|_| {
+                anyhow!("{} isn't set, it's set in GitLab CI jobs", CI_JOB_TOKEN_ENV)
+            })?;
+            fetch = fetch.with_auth(Auth::JobToken(job_token));
+        }
+        let mut p = CloneParams::new(fetch);
+
+        let include = with_globs(self.include, self.include_glob);
+        let exclude = with_globs(self.exclude, self.exclude_glob);
+        if include.is_some() || exclude.is_some() {
+            p.patterns = Some(FilterPatterns {
+                include: include.unwrap_or_default(),
+                exclude: exclude.unwrap_or_default(),
+                ignore_case: self.ignore_case,
+            });
+        }
+        if let Some(path) = self.filter_file {
+            let rules = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read the filter file {}", path.display()))?;
+            p.ignore = Some(IgnoreRules::parse(&rules, self.ignore_case)?);
+        }
+        p.filter.visibility = self.visibility;
+        p.filter.archived = match (self.skip_archived, self.only_archived) {
+            (true, _) => Some(false),
+            (_, true) => Some(true),
+            _ => None,
+        };
+        p.filter.forks = match (self.no_forks, self.only_forks) {
+            (true, _) => Some(false),
+            (_, true) => Some(true),
+            _ => None,
+        };
+        p.filter.active_since = self.active_since;
+        p.filter.inactive_since = self.inactive_since;
+        p.filter.min_size = self.min_size;
+        p.filter.max_size = self.max_size;
+        p.filter.topics = self.topic;
+        p.filter.languages = self.language;
+        p.objects_per_page = self.objects_per_page;
+        p.keyset_pagination = self.keyset_pagination;
+        p.graphql = self.graphql;
+        p.list_concurrency = self.list_concurrency;
+        p.limit = self.limit;
+        p.only_owned = self.only_owned;
+        p.only_membership = self.only_membership;
+        p.group = self.group;
+        p.with_shared = !self.no_shared;
+        p.user = self.user;
+        p.all_personal = self.all_personal;
+        if let Some(path) = self.projects_from {
+            p.projects = Some(read_projects(&path)?);
+        } else if token == JOB_TOKEN {
+            // the API doesn't list projects for job tokens
+            let project = std::env::var(CI_PROJECT_PATH_ENV).map_err(|_| {
+                anyhow!(
+                    "Projects can't be listed with the job token, pass them with --projects-from"
+                )
+            })?;
+            p.projects = Some(vec![project]);
+        }
+        p.retry = self.network.retry();
+        p.rate_limiter = self.network.rate_limiter()?;
+        p.bandwidth_limit = self.network.bandwidth_limit;
+        p.http = self.network.http();
+        p.git_timeout = self.network.git_timeout.map(Duration::from_secs);
+        p.git_credential_helper = self.network.git_credential_helper;
+        p.git_path = self.network.git_path.clone();
+        p.git_args = self.network.git_arg.clone();
+        p.ssh_host_key_checking = self.network.ssh_strict_host_key_checking;
+        p.known_hosts = self.network.known_hosts()?;
+
+        Ok(p)
+    }
+}
+
+impl LocalArgs {
+    fn apply(self, p: &mut CloneParams) {
+        p.dst = self.dst;
+        p.dst_rules = self.dst_map;
+        p.disable_hierarchy = self.disable_hierarchy
+            || self.hierarchy_separator.is_some()
+            || self.hierarchy_depth.is_some();
+        p.hierarchy_separator = self.hierarchy_separator;
+        p.hierarchy_depth = self.hierarchy_depth.unwrap_or_default();
+        p.mirror = self.mirror;
+    }
+}
+
+/// How a run is done and shown, besides its parameters
+struct RunOptions {
+    dry_run: bool,
+    tui: bool,
+    events_jsonl: Option<String>,
+    quiet: bool,
+    /// Runs of the daemon share its handler of interrupts
+    in_daemon: bool,
+}
+
+impl RunOptions {
+    /// Events written to stdout replace the human output
+    fn events_to_stdout(&self) -> bool {
+        self.events_jsonl.as_deref() == Some(events::STDOUT)
+    }
+}
+
+impl CloneArgs {
+    async fn into_params(self) -> Result<(CloneParams, RunOptions)> {
+        let mut p = self.fetch.into_params().await?;
+        self.local.apply(&mut p);
+        match (self.download_concurrency, self.concurrency_limit) {
+            (Some(limit), _) | (None, ConcurrencyLimit::Fixed(limit)) => {
+                p.concurrency_limit = limit
+            }
+            (None, ConcurrencyLimit::Auto) => {
+                p.concurrency_limit = MAX_ADAPTIVE_CONCURRENCY;
+                p.adaptive_concurrency = true;
+            }
+        }
+        p.max_failures = match (self.continue_on_error, self.fail_fast) {
+            (true, _) => None,
+            (_, true) => Some(1),
+            _ => self.max_failures,
+        };
+        p.download_ssh = self.download_ssh;
+        if !self.download_ssh
+            && (self.download_ssh_key.is_some()
+                || self.download_ssh_port.is_some()
+                || !self.download_ssh_option.is_empty())
+        {
+            bail!("The --download-ssh-* flags require --download-ssh");
+        }
+        p.download_ssh_options.key = ssh_key(self.download_ssh_key)?;
+        p.download_ssh_options.port = self.download_ssh_port;
+        p.download_ssh_options.options = self.download_ssh_option;
+        p.clear_dst = self.clear_dst;
+        p.only_master = self.only_master;
+        p.fetch_options.depth = self.depth;
+        p.fetch_options.since = self.since;
+        p.fetch_options.filter = self.filter;
+        p.fetch_options.branches = self
+            .branches
+            .iter()
+            .map(|b| regex::Regex::new(&cloner::glob_to_regex(b)))
+            .collect::<std::result::Result<_, _>>()
+            .context("Invalid --branches pattern")?;
+        p.fetch_options.hidden_refs = self.hidden_refs;
+        p.gc = self.gc || self.gc_aggressive;
+        p.gc_aggressive = self.gc_aggressive;
+        p.dedupe_forks = self.dedupe_forks;
+        p.git_backend = self.git_backend;
+        if p.git_backend == GitBackend::Gitoxide {
+            check_gitoxide(&p)?;
+        }
+        p.bundle_dir = self.bundle_dir;
+        p.incremental_bundles = self.incremental_bundles;
+        p.incremental = self.incremental;
+        p.resume = self.resume;
+        p.shutdown = Some(Arc::new(Shutdown::new(Duration::from_secs(
+            self.shutdown_timeout,
+        ))));
+        p.prune_local = self.prune_local;
+        p.prune_to_trash = self.prune_to_trash;
+        p.prune_dry_run = self.prune_dry_run;
+        p.force_prune = self.force_prune;
+        p.report_file = self.report_file;
+        p.export_archive = self.export_archive;
+        p.wikis = self.wikis;
+        p.snippets = self.snippets;
+        p.personal_snippets = self.personal_snippets;
+        p.designs = self.designs;
+        p.lfs = self.lfs;
+        p.with_issues = self.with_issues;
+        p.with_merge_requests = self.with_merge_requests;
+        p.with_hooks = self.with_hooks;
+        p.include_hook_secrets = self.include_hook_secrets;
+        p.with_badges = self.with_badges;
+        p.with_deploy_keys = self.with_deploy_keys;
+        p.with_members = self.with_members;
+        p.with_registry = self.with_registry;
+        p.with_packages = self.with_packages;
+        p.with_releases = self.with_releases;
+        p.with_labels = self.with_labels;
+        p.with_ci_variables = self.with_ci_variables;
+        p.include_masked_variables = self.include_masked_variables;
+        p.ci_variables_passphrase = self.ci_variables_passphrase;
+        p.with_metadata = self.with_metadata;
+        let run = RunOptions {
+            dry_run: self.dry_run,
+            tui: self.tui,
+            events_jsonl: self.events_jsonl,
+            quiet: false,
+            in_daemon: false,
+        };
+        if run.tui && run.events_to_stdout() {
+            bail!("The --tui flag can't be used with --events-jsonl to stdout");
+        }
+        Ok((p, run))
+    }
+}
+
+impl BackupArgs {
+    fn apply(self, p: &mut CloneParams) -> Result<()> {
+        let bt = match (self.bt_file, self.bt, &self.bu) {
+            (Some(path), _, _) => Some(read_token(&path)?),
+            (None, Some(token), _) => Some(token),
+            (None, None, Some(url)) => fallback_token("bt").or_else(|| tokens::find(url)),
+            (None, None, None) => None,
+        };
+        p.backup = match (self.bu, bt) {
+            (Some(url), Some(token)) => Some(BackupOptions::new(
+                url,
+                token,
+                self.bg,
+                self.backup_provider,
+            )?),
+            (None, None) if self.bg.is_none() => None,
+            _ => bail!(UPLOAD_ERR),
+        };
+        if p.fetch_options.filter.is_some() {
+            bail!("Partial clones (--filter) can't be pushed to a backup");
+        }
+        if p.fetch_options.is_shallow() {
+            bail!("Shallow clones (--depth, --since) can't be pushed to a backup");
+        }
+        p.upload_ssh = self.upload_ssh;
+        if !self.upload_ssh
+            && (self.upload_ssh_key.is_some()
+                || self.upload_ssh_port.is_some()
+                || !self.upload_ssh_option.is_empty())
+        {
+            bail!("The --upload-ssh-* flags require --upload-ssh");
+        }
+        p.upload_ssh_options.key = ssh_key(self.upload_ssh_key)?;
+        p.upload_ssh_options.port = self.upload_ssh_port;
+        p.upload_ssh_options.options = self.upload_ssh_option;
+        p.upload_concurrency = self.upload_concurrency;
+        p.disable_sync_date = self.disable_sync_date;
+        if self.prune_backup.is_some() && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --prune-backup flag is supported only for a GitLab backup");
+        }
+        p.prune_backup = self.prune_backup;
+        p.renames = self.rename;
+        if self.sync_badges && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-badges flag is supported only for a GitLab backup");
+        }
+        p.sync_badges = self.sync_badges;
+        if self.sync_deploy_keys && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-deploy-keys flag is supported only for a GitLab backup");
+        }
+        p.sync_deploy_keys = self.sync_deploy_keys;
+        if self.sync_members && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-members flag is supported only for a GitLab backup");
+        }
+        p.sync_members = self.sync_members;
+        if self.sync_packages && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-packages flag is supported only for a GitLab backup");
+        }
+        p.sync_packages = self.sync_packages;
+        if self.sync_labels && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-labels flag is supported only for a GitLab backup");
+        }
+        p.sync_labels = self.sync_labels;
+        if self.sync_ci_variables && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-ci-variables flag is supported only for a GitLab backup");
+        }
+        p.sync_ci_variables = self.sync_ci_variables;
+        if self.sync_metadata && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-metadata flag is supported only for a GitLab backup");
+        }
+        p.sync_metadata = self.sync_metadata;
+        if self.sync_avatars && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-avatars flag is supported only for a GitLab backup");
+        }
+        p.sync_avatars = self.sync_avatars;
+        if self.sync_protected_branches && self.backup_provider != BackupProvider::Gitlab {
+            bail!("The --sync-protected-branches flag is supported only for a GitLab backup");
+        }
+        p.sync_protected_branches = self.sync_protected_branches;
+        Ok(())
+    }
+}
+
+impl RestoreArgs {
+    fn into_params(self) -> Result<(RestoreParams, bool)> {
+        let url = self
+            .target_url
+            .ok_or_else(|| anyhow!("The --target-url flag is required"))?;
+        let token = match (self.target_token_file, self.target_token) {
+            (Some(path), _) => read_token(&path)?,
+            (None, Some(token)) => token,
+            (None, None) => fallback_token("target_token")
+                .or_else(|| tokens::find(&url))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The --target-token flag is required, or store the token with `auth login`"
+                    )
+                })?,
+        };
+        let src = self
+            .src
+            .ok_or_else(|| anyhow!("The --src flag is required"))?;
+        let mut p = RestoreParams::new(TargetGitlabOptions::new(url, token)?, src);
+        p.group = self.target_group;
+        p.archives = self.archives;
+        p.with_labels = self.with_labels;
+        p.concurrency_limit = self.concurrency_limit;
+        p.upload_ssh = self.upload_ssh;
+        if !self.upload_ssh
+            && (self.upload_ssh_key.is_some()
+                || self.upload_ssh_port.is_some()
+                || !self.upload_ssh_option.is_empty())
+        {
+            bail!("The --upload-ssh-* flags require --upload-ssh");
+        }
+        p.upload_ssh_options.key = ssh_key(self.upload_ssh_key)?;
+        p.upload_ssh_options.port = self.upload_ssh_port;
+        p.upload_ssh_options.options = self.upload_ssh_option;
+        p.retry = self.network.retry();
+        p.rate_limiter = self.network.rate_limiter()?;
+        p.bandwidth_limit = self.network.bandwidth_limit;
+        p.http = self.network.http();
+        p.git_timeout = self.network.git_timeout.map(Duration::from_secs);
+        p.git_credential_helper = self.network.git_credential_helper;
+        p.git_path = self.network.git_path.clone();
+        p.git_args = self.network.git_arg.clone();
+        p.ssh_host_key_checking = self.network.ssh_strict_host_key_checking;
+        p.known_hosts = self.network.known_hosts()?;
+        Ok((p, self.dry_run))
+    }
+}
+
+fn print_projects(projects: &[types::Project]) {
+    for p in projects {
+        println!(
+            "{: <32} (id: {}, path: {})",
+            p.name, p.id, p.path_with_namespace
+        );
+    }
+}
+
+fn print_report(report: &Report) {
+    if report.new.is_empty()
+        && report.updated.is_empty()
+        && report.disappeared.is_empty()
+        && report.failed.is_empty()
+    {
+        println!("No changes since the previous run");
+    }
+    print_changes(report);
+    print_stats(report);
+}
+
+fn print_changes(report: &Report) {
+    let sections = [
+        ("New", &report.new),
+        ("Updated", &report.updated),
+        ("Disappeared", &report.disappeared),
+    ];
+    for (title, paths) in sections {
+        if paths.is_empty() {
+            continue;
+        }
+        println!("{} projects ({}):", title, paths.len());
+        for path in paths {
+            println!("  {}", path);
+        }
+    }
+    print!("{}", failure_summary(report));
+}
+
+/// Failed projects grouped by the class of failures with suggested causes,
+/// so they aren't lost among log lines of a large run
+fn failure_summary(report: &Report) -> String {
+    let mut groups: BTreeMap<FailureKind, Vec<&FailedProject>> = BTreeMap::new();
+    for f in &report.failed {
+        groups.entry(f.kind).or_default().push(f);
+    }
+    let mut summary = String::new();
+    if groups.is_empty() {
+        return summary;
+    }
+    summary += &format!("Failed projects ({}):\n", report.failed.len());
+    for (kind, failed) in groups {
+        summary += &format!("  {} ({})", kind.title(), failed.len());
+        if let Some(cause) = kind.cause() {
+            summary += &format!(", possible causes: {}", cause);
+        }
+        summary += "\n";
+        for f in failed {
+            summary += &format!("    {}: {}\n", f.path, short_error(&f.error));
+        }
+    }
+    summary
+}
+
+/// The line of an error with the reason, git errors start with progress lines
+fn short_error(error: &str) -> &str {
+    let mut lines = error.lines().map(str::trim).filter(|l| !l.is_empty());
+    let first = lines.next().unwrap_or_default();
+    let reason = error.lines().map(str::trim).find(|l| {
+        ["fatal:", "error:", "! [", "remote: "]
+            .iter()
+            .any(|p| l.starts_with(p))
+            || l.contains("Permission denied")
+    });
+    reason.unwrap_or(first)
+}
+
+fn print_stats(report: &Report) {
+    let stats = &report.stats;
+    println!(
+        "Summary: {} succeeded, {} skipped, {} failed in {}, received {}",
+        stats.succeeded,
+        stats.skipped,
+        report.failed.len(),
+        HumanDuration(Duration::from_secs_f64(stats.duration_secs)),
+        HumanBytes(stats.received_bytes),
+    );
+    if stats.slowest.is_empty() {
+        return;
+    }
+    println!("Slowest projects:");
+    for timing in &stats.slowest {
+        println!(
+            "  {}: {:.1}s, received {}",
+            timing.path,
+            timing.duration_secs,
+            HumanBytes(timing.received_bytes)
+        );
+    }
+}
+
+async fn dry_run(p: &CloneParams) -> Result<()> {
+    let projects = cloner::list(p).await?;
+    if let Some(g) = cloner::backup_group(p).await? {
+        println!(
+            "Backup group:   {} (id: {}, path: {})",
+            g.name, g.id, g.full_path
+        )
+    };
+    println!("Local out dir: {}", p.local_dir());
+    println!();
+    print_projects(&projects);
+    Ok(())
+}
+
+async fn clone(mut p: CloneParams, run: RunOptions) -> Result<()> {
+    if run.dry_run {
+        return dry_run(&p).await;
+    }
+    let events = match &run.events_jsonl {
+        Some(path) => Some(Arc::new(Events::open(path)?)),
+        None => None,
+    };
+    if run.tui && run.quiet {
+        bail!("The --tui flag can't be used with --quiet");
+    }
+    match &p.shutdown {
+        Some(shutdown) if !run.in_daemon => handle_interrupts(shutdown.clone(), run.tui),
+        _ => {}
+    }
+    if run.events_to_stdout() || run.quiet {
+        return clone_quiet(p, events).await;
+    }
+    if run.tui {
+        return clone_tui(p, events).await;
+    }
+
+    let prune_dry_run = p.prune_dry_run;
+    let bars = Arc::new(Mutex::new(Bars::new()));
+    let ticker = tokio::spawn(Bars::tick(bars.clone()));
+    let on_progress = bars.clone();
+    let show: ProgressCallback = Box::new(move |progress| {
+        let mut bars = on_progress.lock().unwrap();
+        match progress {
+            Progress::Started { total } => bars.start(total),
+            Progress::Queued { .. } => {}
+            Progress::Fetching { project, dir } => bars.fetching(project, dir),
+            Progress::Pushing { project } => bars.pushing(project),
+            Progress::Cloned { project } | Progress::Skipped { project } => {
+                bars.done(project, false)
+            }
+            Progress::Failed { project, .. } => bars.done(project, true),
+            Progress::Pruned { path, backup } => {
+                let kind = if backup { "backup project" } else { "local" };
+                if prune_dry_run {
+                    bars.println(format!("would prune {} {}", kind, path));
+                } else {
+                    bars.println(format!("pruned {} {}", kind, path));
+                }
+            }
+        }
+    });
+    p.on_progress = Some(with_events(events.clone(), show));
+
+    let report = cloner::clone(p).await.map_err(resume_hint);
+    if let Some(events) = &events {
+        events.finished(&report);
+    }
+    ticker.abort();
+    bars.lock().unwrap().finish();
+    println!();
+    let report = report?;
+    print_report(&report);
+    check_failed(&report)
+}
+
+/// Only one daemon runs in a directory, the lock is held until the daemon exits
+fn lock_dst(dir: &str) -> Result<std::fs::File> {
+    let path = Path::new(dir).join(STATE_DIR).join(DAEMON_LOCK);
+    std::fs::create_dir_all(path.parent().unwrap_or(Path::new(dir)))?;
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    if file.try_lock().is_err() {
+        bail!("Another daemon is running in {}", dir);
+    }
+    Ok(file)
+}
+
+/// Arguments of runs of `daemon` and `listen`, they're parsed again for every run,
+/// but token files are read once, so a token from stdin (`-`) is used by all runs
+struct RunArgs<'a> {
+    matches: &'a ArgMatches,
+    ft: Option<String>,
+    bt: Option<String>,
+}
+
+impl<'a> RunArgs<'a> {
+    fn new(matches: &'a ArgMatches) -> Result<Self> {
+        let clone = CloneArgs::from_arg_matches(matches)?;
+        let backup = BackupArgs::from_arg_matches(matches)?;
+        Ok(Self {
+            matches,
+            ft: clone.fetch.ft_file.as_deref().map(read_token).transpose()?,
+            bt: backup.bt_file.as_deref().map(read_token).transpose()?,
+        })
+    }
+
+    /// Parameters of the next run
+    async fn params(&self) -> Result<(CloneParams, RunOptions)> {
+        let mut clone = CloneArgs::from_arg_matches(self.matches)?;
+        if let Some(ft) = &self.ft {
+            clone.fetch.ft_file = None;
+            clone.fetch.ft = Some(ft.clone());
+        }
+        let mut backup = BackupArgs::from_arg_matches(self.matches)?;
+        if let Some(bt) = &self.bt {
+            backup.bt_file = None;
+            backup.bt = Some(bt.clone());
+        }
+        let (mut p, run) = clone.into_params().await?;
+        backup.apply(&mut p)?;
+        Ok((p, run))
+    }
+}
+
+/// Runs by the schedule until an interrupt, runs are never started while one is in progress,
+/// scheduled times which pass during a run are skipped
+async fn daemon(args: DaemonArgs, matches: &ArgMatches, quiet: bool) -> Result<()> {
+    let first = CloneArgs::from_arg_matches(matches)?;
+    if first.tui {
+        bail!("The --tui flag can't be used with the daemon");
+    }
+    let run_args = RunArgs::new(matches)?;
+    let shutdown = Arc::new(Shutdown::new(Duration::from_secs(first.shutdown_timeout)));
+    handle_interrupts(shutdown.clone(), false);
+    let mut lock = None;
+    let mut run_now = args.run_now;
+    loop {
+        if !run_now {
+            let next = args.schedule.find_next_occurrence(&Local::now(), false)?;
+            if !quiet {
+                println!("Next run at {}", next.format("%Y-%m-%d %H:%M"));
+            }
+            let wait = (next - Local::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.stopped() => return Ok(()),
+            }
+        }
+        run_now = false;
+
+        let started = Local::now();
+        let prepared = async {
+            let (mut p, run) = run_args.params().await?;
+            if lock.is_none() {
+                lock = Some(lock_dst(&p.local_dir())?);
+            }
+            if let Some(dir) = &args.reports_dir {
+                std::fs::create_dir_all(dir)?;
+                let name = format!("report-{}.json", started.format("%Y%m%d-%H%M%S"));
+                p.report_file = Some(Path::new(dir).join(name).to_string_lossy().to_string());
+            }
+            Ok::<_, anyhow::Error>((p, run))
+        }
+        .await;
+        // e.g. an unreachable instance of OAuth or a held lock, the next run tries again
+        let (mut p, run) = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                tracing::error!("the run can't start: {:#}", e);
+                continue;
+            }
+        };
+        p.shutdown = Some(shutdown.clone());
+        let run = RunOptions {
+            quiet,
+            in_daemon: true,
+            ..run
+        };
+        // a failed run doesn't stop the daemon, the next one retries its projects
+        match clone(p, run).await {
+            Err(e) if exit_code(&e) == EXIT_INTERRUPTED => return Err(e),
+            Err(e) => tracing::error!("the run failed: {:#}", e),
+            Ok(()) => {}
+        }
+        let planned = args.schedule.find_next_occurrence(&started, false)?;
+        if planned < Local::now() {
+            tracing::warn!(
+                "the run took longer than the schedule, the run at {} is skipped",
+                planned.format("%Y-%m-%d %H:%M")
+            );
+        }
+    }
+}
+
+/// Syncs projects of webhooks until an interrupt, webhooks received during a run
+/// are synced together by the next one
+async fn listen(args: ListenArgs, matches: &ArgMatches, quiet: bool) -> Result<()> {
+    let first = CloneArgs::from_arg_matches(matches)?;
+    if first.tui {
+        bail!("The --tui flag can't be used with the listener");
+    }
+    let run_args = RunArgs::new(matches)?;
+    let (p, _) = run_args.params().await?;
+    // runs have only projects of webhooks, all others would be pruned
+    if p.prune_local || p.prune_backup.is_some() || p.clear_dst {
+        bail!("Pruning and --clear-dst can't be used with the listener");
+    }
+    let _lock = lock_dst(&p.local_dir())?;
+    let secret = match args.webhook_secret_file {
+        Some(path) => Some(read_token(&path)?),
+        None => args.webhook_secret,
+    };
+    match secret {
+        None if !args.listen.ip().is_loopback() => bail!(
+            "--webhook-secret is required to listen on {}, webhooks of anyone would be accepted",
+            args.listen
+        ),
+        None => tracing::warn!("no --webhook-secret is set, local webhooks of anyone are accepted"),
+        Some(_) => {}
+    }
+
+    let shutdown = p.shutdown.clone().expect("runs have a shutdown");
+    handle_interrupts(shutdown.clone(), false);
+    let (queue, mut queued) = mpsc::unbounded_channel();
+    let server = tokio::spawn(listen::serve(args.listen, secret, queue, shutdown.clone()));
+    loop {
+        let project = tokio::select! {
+            project = queued.recv() => project,
+            _ = shutdown.stopped() => None,
+        };
+        let Some(project) = project else { break };
+        let mut projects = BTreeSet::from([project]);
+        while let Ok(project) = queued.try_recv() {
+            projects.insert(project);
+        }
+
+        let (mut p, run) = match run_args.params().await {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                tracing::error!(
+                    "the run of {} can't start: {:#}",
+                    projects.into_iter().collect::<Vec<_>>().join(", "),
+                    e
+                );
+                continue;
+            }
+        };
+        p.projects = Some(projects.into_iter().collect());
+        p.listed_in_scope = true;
+        // the last activity of GitLab projects is updated with a delay, pushed projects are
+        // always synced; an interrupted run isn't resumed with projects of other webhooks
+        p.incremental = false;
+        p.resume = false;
+        p.shutdown = Some(shutdown.clone());
+        let run = RunOptions {
+            quiet,
+            in_daemon: true,
+            ..run
+        };
+        match clone(p, run).await {
+            Err(e) if exit_code(&e) == EXIT_INTERRUPTED => return Err(e),
+            Err(e) => tracing::error!("the run failed: {:#}", e),
+            Ok(()) => {}
+        }
+    }
+    server.await?
+}
+
+/// Runs the clone with the dashboard, the report is printed after it's closed
+async fn clone_tui(mut p: CloneParams, events: Option<Arc<Events>>) -> Result<()> {
+    if !std::io::stdout().is_terminal() {
+        bail!("The --tui flag requires a terminal");
+    }
+    let dashboard = Arc::new(Mutex::new(Dashboard::new()));
+    let on_progress = dashboard.clone();
+    let show: ProgressCallback =
+        Box::new(move |progress| on_progress.lock().unwrap().on_progress(progress));
+    p.on_progress = Some(with_events(events.clone(), show));
+    let shutdown = p.shutdown.clone().expect("runs have a shutdown");
+    let tui = Tui::start(dashboard, shutdown)?;
+    let report = cloner::clone(p).await.map_err(resume_hint);
+    if let Some(events) = &events {
+        events.finished(&report);
+    }
+    tui.stop();
+    let report = report?;
+    print_report(&report);
+    check_failed(&report)
+}
+
+/// Runs the clone without output to stdout (for --quiet, or events written to it),
+/// only failed projects are printed to stderr, logs go to stderr
+async fn clone_quiet(mut p: CloneParams, events: Option<Arc<Events>>) -> Result<()> {
+    p.on_progress = Some(with_events(events.clone(), Box::new(|_| {})));
+    let report = cloner::clone(p).await.map_err(resume_hint);
+    if let Some(events) = &events {
+        events.finished(&report);
+    }
+    let report = report?;
+    eprint!("{}", failure_summary(&report));
+    check_failed(&report)
+}
+
+/// Progress bars of a run: the overall one with failures so far, and a spinner with
+/// the stage and the received size for every project in progress
+struct Bars {
+    multi: MultiProgress,
+    overall: Option<ProgressBar>,
+    /// Spinners of projects in progress by paths, with directories of fetched ones
+    active: BTreeMap<String, (ProgressBar, Option<String>)>,
+    failed: usize,
+}
+
+/// How often received sizes of projects in progress are updated
+const BARS_TICK: Duration = Duration::from_millis(500);
+
+impl Bars {
+    fn new() -> Self {
+        Self {
+            multi: MultiProgress::new(),
+            overall: None,
+            active: BTreeMap::new(),
+            failed: 0,
+        }
+    }
+
+    fn start(&mut self, total: usize) {
+        let style = ProgressStyle::with_template(
+            "Cloning: {pos}/{len} [{wide_bar}] {percent}% {eta} {msg}",
+        )
+        .unwrap()
+        .progress_chars("=>-");
+        let bar = self
+            .multi
+            .add(ProgressBar::new(total as u64).with_style(style));
+        self.overall = Some(bar);
+    }
+
+    fn fetching(&mut self, project: &types::Project, dir: &str) {
+        let style = ProgressStyle::with_template("  {spinner} {prefix} {msg} {elapsed}").unwrap();
+        let spinner = self.multi.add(
+            ProgressBar::new_spinner()
+                .with_style(style)
+                .with_prefix(project.path_with_namespace.clone())
+                .with_message("fetching"),
+        );
+        spinner.enable_steady_tick(BARS_TICK);
+        self.active.insert(
+            project.path_with_namespace.clone(),
+            (spinner, Some(dir.to_string())),
+        );
+    }
+
+    fn pushing(&mut self, project: &types::Project) {
+        if let Some((spinner, dir)) = self.active.get_mut(&project.path_with_namespace) {
+            spinner.set_message("pushing");
+            *dir = None;
+        }
+    }
+
+    fn done(&mut self, project: &types::Project, failed: bool) {
+        if let Some((spinner, _)) = self.active.remove(&project.path_with_namespace) {
+            spinner.finish_and_clear();
+            self.multi.remove(&spinner);
+        }
+        if failed {
+            self.failed += 1;
+            self.println(format!("failed {}", project.path_with_namespace));
+        }
+        if let Some(bar) = &self.overall {
+            bar.inc(1);
+            if self.failed > 0 {
+                bar.set_message(format!("({} failed)", self.failed));
+            }
+        }
+    }
+
+    /// Prints the line above the bars
+    fn println(&self, line: String) {
+        if self.multi.is_hidden() {
+            println!("{}", line);
+        } else {
+            let _ = self.multi.println(line);
+        }
+    }
+
+    fn finish(&mut self) {
+        for (spinner, _) in std::mem::take(&mut self.active).into_values() {
+            spinner.finish_and_clear();
+        }
+        if let Some(bar) = &self.overall {
+            bar.finish();
+        }
+    }
+
+    /// Updates received sizes of projects which are fetched
+    async fn tick(bars: Arc<Mutex<Bars>>) {
+        let mut interval = tokio::time::interval(BARS_TICK);
+        loop {
+            interval.tick().await;
+            // sizes are counted without the lock, so they don't block progress of the run
+            let fetching: Vec<(String, String)> = bars
+                .lock()
+                .unwrap()
+                .active
+                .iter()
+                .filter_map(|(path, (_, dir))| Some((path.clone(), dir.clone()?)))
+                .collect();
+            for (path, dir) in fetching {
+                let size = cloner::dir_size(Path::new(&dir));
+                let bars = bars.lock().unwrap();
+                if let Some((spinner, Some(_))) = bars.active.get(&path) {
+                    spinner.set_message(format!("fetching {}", HumanBytes(size)));
+                }
+            }
+        }
+    }
+}
+
+async fn verify(p: CloneParams, quiet: bool) -> Result<()> {
+    let not_cloned = cloner::verify(&p).await?;
+    for project in &not_cloned {
+        println!(
+            "{: <32} not cloned ({})",
+            project.path_with_namespace,
+            p.project_dir(project)
+        );
+    }
+
+    if !not_cloned.is_empty() {
+        let message = format!(
+            "{} projects are not cloned to {}",
+            not_cloned.len(),
+            p.local_dir()
+        );
+        return Err(ProjectsFailed(message).into());
+    }
+    if !quiet {
+        println!("All projects are cloned to {}", p.local_dir());
+    }
+
+    Ok(())
+}
+
+async fn restore(p: RestoreParams, dry: bool, quiet: bool) -> Result<()> {
+    let projects = if dry {
+        restore::find(&p)?
+    } else {
+        exit_on_interrupt();
+        restore::restore(p).await?
+    };
+    if quiet && !dry {
+        return Ok(());
+    }
+    for project in &projects {
+        println!("{: <32} ({})", project.path, project.file);
+    }
+    Ok(())
+}
+
+fn auth(command: AuthCommands) -> Result<()> {
+    match command {
+        AuthCommands::Login { url, token_file } => {
+            let token = match token_file {
+                Some(path) => read_token(&path)?,
+                None => {
+                    let token = rpassword::prompt_password(format!("Token for {}: ", url))
+                        .context("Failed to read the token")?;
+                    let token = token.trim().to_string();
+                    if token.is_empty() {
+                        bail!("The token is empty");
+                    }
+                    token
+                }
+            };
+            tokens::save(&url, &token)?;
+            println!("The token of {} is stored in the keyring", url);
+        }
+        AuthCommands::Logout { url } => {
+            if tokens::remove(&url)? {
+                println!("The token of {} is removed from the keyring", url);
+            } else {
+                println!("No token of {} is stored in the keyring", url);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Logs go to the terminal (the log pane of the dashboard, or stderr if events are written
+/// to stdout), and to the log file if it's passed
+fn init_logging(cli: &Cli, matches: &ArgMatches) -> Result<()> {
+    let log_level = match cli.verbose {
+        0 => tracing::Level::ERROR,
+        1 => tracing::Level::WARN,
+        2 => tracing::Level::INFO,
+        3 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    let run_matches = matches.subcommand().map_or(matches, |(_, m)| m);
+    let tui = run_matches
+        .try_get_one::<bool>("tui")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or(false);
+    let events_to_stdout = run_matches
+        .try_get_one::<String>("events_jsonl")
+        .ok()
+        .flatten()
+        .is_some_and(|path| path == events::STDOUT);
+    // the dashboard takes the terminal, so log lines are shown in its log pane
+    let mut layers = vec![if tui {
+        log_layer(
+            LogFormat::Text,
+            || tui::LogWriter,
+            false,
+            log_level.max(tracing::Level::INFO),
+        )
+    } else if events_to_stdout || cli.quiet {
+        log_layer(cli.log_format, std::io::stderr, true, log_level)
+    } else {
+        log_layer(cli.log_format, std::io::stdout, true, log_level)
+    }];
+    if let Some(path) = &cli.log_file {
+        let file = RotatingFile::open(
+            path.clone(),
+            cli.log_max_size,
+            cli.log_rotation,
+            cli.log_keep,
+        )?;
+        layers.push(log_layer(
+            cli.log_format,
+            Mutex::new(file),
+            false,
+            log_level.max(tracing::Level::INFO),
+        ));
+    }
+    tracing_subscriber::registry().with(layers).init();
+    Ok(())
+}
+
+/// JSON lines have fields of events at the top level, and fields of the current span
+/// (the project) in `span`
+fn log_layer<W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+    level: tracing::Level,
+) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    let filter = LevelFilter::from_level(level);
+    match format {
+        LogFormat::Text => layer.with_filter(filter).boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_filter(filter)
+            .boxed(),
+    }
+}
+
+pub async fn run() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut cmd = Cli::command();
+    let config = config::load(&args)?;
+    // a job of GitLab CI backs up its instance by default
+    if std::env::var_os(CI_JOB_TOKEN_ENV).is_some() {
+        if let Ok(url) = std::env::var(CI_SERVER_URL_ENV) {
+            config::set_default(&mut cmd, "fu", &[url]);
+        }
+    }
+    if let Some(config) = config {
+        let _ = CONFIG_TOKENS.set(config::apply(&mut cmd, config)?);
+    }
+    let matches = cmd
+        .try_get_matches_from_mut(args)
+        .unwrap_or_else(|e| exit_usage(e));
+    // flags of the flat form are ignored by subcommands, so they aren't allowed together
+    if matches.subcommand().is_some() {
+        let flat_arg = matches.ids().find(|id| {
+            !GLOBAL_ARGS.contains(&id.as_str())
+                && matches.value_source(id.as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(id) = flat_arg {
+            let e = cmd.error(
+                ErrorKind::ArgumentConflict,
+                format!("the argument '--{}' cannot be used with a subcommand", id),
+            );
+            exit_usage(e);
+        }
+    }
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| exit_usage(e));
+
+    init_logging(&cli, &matches)?;
+    let quiet = cli.quiet;
+
+    match cli.command {
+        Some(Commands::Clone(args)) => {
+            let (p, run) = args.into_params().await?;
+            clone(p, RunOptions { quiet, ..run }).await
+        }
+        Some(Commands::Backup {
+            clone: args,
+            backup,
+        }) => {
+            let (mut p, run) = args.into_params().await?;
+            backup.apply(&mut p)?;
+            if p.backup.is_none() {
+                bail!(UPLOAD_ERR);
+            }
+            clone(p, RunOptions { quiet, ..run }).await
+        }
+        Some(Commands::List(args)) => {
+            print_projects(&cloner::list(&args.into_params().await?).await?);
+            Ok(())
+        }
+        Some(Commands::Verify { fetch, local }) => {
+            let mut p = fetch.into_params().await?;
+            local.apply(&mut p);
+            verify(p, quiet).await
+        }
+        Some(Commands::Restore(args)) => {
+            let (p, dry) = args.into_params()?;
+            restore(p, dry, quiet).await
+        }
+        Some(Commands::Auth { command }) => auth(command),
+        Some(Commands::Daemon { daemon: args, .. }) => {
+            let matches = matches.subcommand_matches("daemon").unwrap_or(&matches);
+            daemon(args, matches, quiet).await
+        }
+        Some(Commands::Listen { listen: args, .. }) => {
+            let matches = matches.subcommand_matches("listen").unwrap_or(&matches);
+            listen(args, matches, quiet).await
+        }
+        Some(Commands::Completions { shell }) => {
+            let mut cmd = Cli::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+            Ok(())
+        }
+        None => {
+            let (mut p, run) = cli.clone.into_params().await?;
+            cli.backup.apply(&mut p)?;
+            clone(p, RunOptions { quiet, ..run }).await
+        }
+    }
 }
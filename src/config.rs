@@ -0,0 +1,114 @@
+use anyhow::{bail, Context, Result};
+use clap::Command;
+use std::collections::BTreeMap;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// A config file which is read from the working directory if `--config` isn't passed
+const DEFAULT_PATH: &str = "gitlobster.toml";
+const PATH_ENV: &str = "GTLBSTR_CONFIG";
+const PATH_FLAG: &str = "--config";
+/// Tokens aren't defaults of arguments, `--help` would print them, [`apply`] returns them
+/// to be used when neither the flag nor its env variables are set
+pub const TOKEN_IDS: [&str; 3] = ["ft", "bt", "target_token"];
+/// Other secrets are defaults hidden from `--help`
+const SECRET_IDS: [&str; 2] = ["webhook_secret", "ci_variables_passphrase"];
+
+/// Looks for the config path in the command line args, then in the env
+fn find_path(args: &[String]) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == PATH_FLAG {
+            return args.next().cloned();
+        }
+        if let Some(path) = arg.strip_prefix(&format!("{}=", PATH_FLAG)) {
+            return Some(path.to_string());
+        }
+    }
+    std::env::var(PATH_ENV).ok()
+}
+
+/// Reads the config file, if an explicitly passed file doesn't exist it's an error
+pub fn load(args: &[String]) -> Result<Option<Table>> {
+    let path = match find_path(args) {
+        Some(path) => path,
+        None if Path::new(DEFAULT_PATH).exists() => DEFAULT_PATH.to_string(),
+        None => return Ok(None),
+    };
+
+    let content =
+        std::fs::read_to_string(&path).with_context(|| format!("cannot read config {}", path))?;
+    let table = content
+        .parse::<Table>()
+        .with_context(|| format!("invalid config {}", path))?;
+
+    Ok(Some(table))
+}
+
+fn value_to_string(key: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => bail!("unsupported value type of the config option {}", key),
+    }
+}
+
+/// Sets default values of the argument in the command and all its subcommands,
+/// the argument becomes optional, returns false if the argument isn't found
+pub fn set_default(cmd: &mut Command, id: &str, values: &[String]) -> bool {
+    let mut c = std::mem::take(cmd);
+    let mut found = c.get_arguments().any(|a| a.get_id() == id);
+    if found {
+        let secret = SECRET_IDS.contains(&id);
+        c = c.mut_arg(id, |a| {
+            a.required(false)
+                .default_values(values.to_vec())
+                .hide_default_value(secret)
+        });
+    }
+
+    let names: Vec<String> = c.get_subcommands().map(|s| s.get_name().into()).collect();
+    for name in names {
+        c = c.mut_subcommand(name, |mut sub| {
+            found |= set_default(&mut sub, id, values);
+            sub
+        });
+    }
+
+    *cmd = c;
+    found
+}
+
+/// Sets values from the config as defaults of the corresponding arguments,
+/// so values from the command line and the env always take precedence,
+/// returns tokens of the config by argument ids
+pub fn apply(cmd: &mut Command, config: Table) -> Result<BTreeMap<String, String>> {
+    let mut tokens = BTreeMap::new();
+    for (key, value) in config {
+        let values = match value {
+            Value::Array(values) => values
+                .into_iter()
+                .map(|v| value_to_string(&key, v))
+                .collect::<Result<Vec<String>>>()?,
+            value => vec![value_to_string(&key, value)?],
+        };
+
+        let id = key.replace('-', "_");
+        if TOKEN_IDS.contains(&id.as_str()) {
+            match <[String; 1]>::try_from(values) {
+                Ok([token]) => tokens.insert(id, token),
+                Err(_) => bail!("the config option {} must be a single token", key),
+            };
+            continue;
+        }
+        if id == "config" || !set_default(cmd, &id, &values) {
+            bail!("unknown config option {}", key);
+        }
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,143 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::cloner::FailureKind;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the library
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid clone parameters, e.g. a malformed URL or a filter pattern
+    Params(String),
+    /// A request to the GitLab API failed
+    Api(reqwest::Error),
+    /// A git command failed
+    Git(String),
+    /// A filesystem operation failed
+    Io(std::io::Error),
+    /// A GitLab project export or import failed
+    Export(String),
+    /// A container image copy (skopeo) failed
+    Registry(String),
+    /// Reading or writing the sync state database failed
+    State(rusqlite::Error),
+    /// A git command didn't finish in time and was killed
+    Timeout(String),
+    /// A git command (or ssh) needed a prompt, e.g. a passphrase or trusting a host key
+    Prompt(String),
+    /// Logging in to an instance failed
+    Auth(String),
+    /// A GraphQL query returned errors or unexpected data
+    Graphql(String),
+    /// The run is aborted because too many projects failed
+    Aborted(String),
+    /// The run is stopped by an interrupt before all projects are synced
+    Interrupted(String),
+}
+
+impl Error {
+    /// Checks that a git command or an API request timed out
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Api(e) => e.is_timeout(),
+            _ => false,
+        }
+    }
+
+    /// Checks that a git command failed because it couldn't ask the user
+    pub fn is_prompt(&self) -> bool {
+        matches!(self, Error::Prompt(_))
+    }
+
+    /// Checks that retrying can help: network errors, timeouts and 5xx responses,
+    /// but not denied access, prompts or rejected pushes
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Api(e) if e.status().is_some_and(|s| s.is_server_error()) => true,
+            _ => matches!(
+                FailureKind::of(self),
+                FailureKind::Network | FailureKind::Timeout
+            ),
+        }
+    }
+
+    /// Checks that the error stops the whole run rather than fails a project,
+    /// the sync state can't record results of other projects
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Error::State(_) | Error::Aborted(_) | Error::Interrupted(_)
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Params(e) => write!(f, "invalid params: {}", e),
+            Error::Api(e) => write!(f, "gitlab api error: {}", e),
+            Error::Git(e) => write!(f, "git error: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Export(e) => write!(f, "export error: {}", e),
+            Error::Registry(e) => write!(f, "registry error: {}", e),
+            Error::State(e) => write!(f, "state error: {}", e),
+            Error::Timeout(e) => write!(f, "timeout: {}", e),
+            Error::Prompt(e) => write!(f, "prompt error: {}", e),
+            Error::Auth(e) => write!(f, "auth error: {}", e),
+            Error::Graphql(e) => write!(f, "graphql error: {}", e),
+            Error::Aborted(e) => write!(f, "aborted: {}", e),
+            Error::Interrupted(e) => write!(f, "interrupted: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Api(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::State(e) => Some(e),
+            Error::Params(_)
+            | Error::Git(_)
+            | Error::Export(_)
+            | Error::Registry(_)
+            | Error::Timeout(_)
+            | Error::Prompt(_)
+            | Error::Auth(_)
+            | Error::Graphql(_)
+            | Error::Aborted(_)
+            | Error::Interrupted(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Api(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::State(e)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Params(format!("invalid url: {}", e))
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::Params(format!("invalid pattern: {}", e))
+    }
+}
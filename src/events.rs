@@ -0,0 +1,103 @@
+//! `--events-jsonl`: a JSON object per line for every transition of a run,
+//! so wrappers and monitoring can follow it without parsing the human output
+
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use gitlobster::cloner::{Progress, ProgressCallback, Report};
+use gitlobster::gitlab::types;
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// The path of `--events-jsonl` for stdout
+pub const STDOUT: &str = "-";
+
+pub struct Events {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Events {
+    /// Events are appended to the file, so runs can share it
+    pub fn open(path: &str) -> Result<Self> {
+        let out: Box<dyn Write + Send> = if path == STDOUT {
+            Box::new(io::stdout())
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open the events file {}", path))?;
+            Box::new(file)
+        };
+        Ok(Self {
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn on_progress(&self, progress: Progress) {
+        let event = match progress {
+            Progress::Started { total } => json!({"event": "started", "total": total}),
+            Progress::Queued { project } => project_event("queued", project),
+            Progress::Fetching { project, dir } => {
+                let mut event = project_event("fetching", project);
+                event["dir"] = dir.into();
+                event
+            }
+            Progress::Pushing { project } => project_event("pushing", project),
+            Progress::Cloned { project } => project_event("synced", project),
+            Progress::Skipped { project } => project_event("skipped", project),
+            Progress::Failed { project, error } => {
+                let mut event = project_event("failed", project);
+                event["error"] = error.into();
+                event
+            }
+            Progress::Pruned { path, backup } => {
+                json!({"event": "pruned", "path": path, "backup": backup})
+            }
+        };
+        self.write(event)
+    }
+
+    /// The last event of a run, with totals or the error which stopped it
+    pub fn finished<E: Display>(&self, report: &std::result::Result<Report, E>) {
+        let event = match report {
+            Ok(report) => json!({
+                "event": "finished",
+                "succeeded": report.stats.succeeded,
+                "skipped": report.stats.skipped,
+                "failed": report.failed.len(),
+                "received_bytes": report.stats.received_bytes,
+                "duration_secs": report.stats.duration_secs,
+            }),
+            Err(e) => json!({"event": "finished", "error": e.to_string()}),
+        };
+        self.write(event)
+    }
+
+    fn write(&self, mut event: Value) {
+        event["time"] = chrono::Utc::now().to_rfc3339().into();
+        let mut out = self.out.lock().unwrap();
+        // a broken events output doesn't stop the run
+        if let Err(e) = writeln!(out, "{}", event).and_then(|_| out.flush()) {
+            warn!("failed to write an event: {}", e);
+        }
+    }
+}
+
+fn project_event(name: &str, project: &types::Project) -> Value {
+    json!({"event": name, "project": project.path_with_namespace, "id": project.id})
+}
+
+/// Writes events of the progress before passing it to the display
+pub fn with_events(events: Option<Arc<Events>>, on_progress: ProgressCallback) -> ProgressCallback {
+    match events {
+        Some(events) => Box::new(move |progress| {
+            events.on_progress(progress);
+            on_progress(progress)
+        }),
+        None => on_progress,
+    }
+}
@@ -1,56 +1,759 @@
-use anyhow::{bail, Result};
+use crate::error::{Error, Result};
+use crate::http::REDACTED;
+use chrono::NaiveDate;
+use regex::Regex;
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokio::process::Command;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
+
+/// Config options with secrets for all git commands, they're passed in `GIT_CONFIG_*`
+/// environment variables, so they aren't shown in logs and lists of processes
+static SECRET_CONFIG: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Sets (or unsets with `None`) the config option with a secret for all git commands
+pub fn set_secret_config(key: &str, value: Option<String>) {
+    let mut config = SECRET_CONFIG.lock().unwrap();
+    match value {
+        Some(value) => config.insert(key.to_string(), value),
+        None => config.remove(key),
+    };
+}
+
+/// Environment variables of all git commands, they take precedence over inherited ones
+static ENV: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Sets (or unsets with `None`) the environment variable for all git commands
+pub fn set_env(name: &str, value: Option<String>) {
+    let mut env = ENV.lock().unwrap();
+    match value {
+        Some(value) => env.insert(name.to_string(), value),
+        None => env.remove(name),
+    };
+}
+
+/// The git executable, `git` from `PATH` if it isn't set
+static EXECUTABLE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Arguments passed to all git commands before the command name
+static ARGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Sets the git executable (`None` for `git` from `PATH`) and arguments passed to
+/// all git commands before the command name, e.g. `-c protocol.version=2`
+pub fn set_executable(path: Option<String>, args: Vec<String>) {
+    *EXECUTABLE.lock().unwrap() = path;
+    *ARGS.lock().unwrap() = args;
+}
+
+/// Git commands running longer are killed
+static TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Sets (or removes with `None`) the timeout of every git command
+pub fn set_timeout(timeout: Option<Duration>) {
+    *TIMEOUT.lock().unwrap() = timeout;
+}
+
+/// Git commands run in their own process group
+static OWN_PROCESS_GROUP: AtomicBool = AtomicBool::new(false);
+
+/// Runs git commands in their own process group, so interrupts of the terminal reach
+/// only gitlobster, which lets them finish or cancels them itself
+pub fn set_own_process_group(own: bool) {
+    OWN_PROCESS_GROUP.store(own, Ordering::Relaxed);
+}
+
+/// Process groups of cancelled git commands which are killed and may still be exiting
+static KILLED_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// How long [`wait_killed`] waits for killed process groups
+const KILLED_WAIT: Duration = Duration::from_secs(10);
+
+/// The own process group of a running git command, when the command is dropped before it
+/// exits (it's cancelled or timed out), the whole group is killed: git and its children
+/// (ssh, remote helpers, pack-objects), not only git as `kill_on_drop` does
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    fn exited(&mut self) {
+        self.0 = None;
+    }
+
+    /// Kills the group and waits until its processes exit
+    async fn kill(mut self) {
+        if let Some(pgid) = self.0.take() {
+            kill_group(pgid);
+            wait_exited(vec![pgid]).await;
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            kill_group(pgid);
+            KILLED_GROUPS.lock().unwrap().push(pgid);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(pgid: u32) {
+    // SAFETY: killpg only sends the signal to the group of the git command
+    unsafe { libc::killpg(pgid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[cfg(not(unix))]
+fn kill_group(_: u32) {}
+
+#[cfg(unix)]
+fn group_alive(pgid: u32) -> bool {
+    // SAFETY: the signal 0 only checks that the group exists
+    unsafe { libc::killpg(pgid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn group_alive(_: u32) -> bool {
+    false
+}
+
+async fn wait_exited(mut groups: Vec<u32>) {
+    let started = Instant::now();
+    loop {
+        groups.retain(|&pgid| group_alive(pgid));
+        if groups.is_empty() {
+            return;
+        }
+        if started.elapsed() >= KILLED_WAIT {
+            warn!("{} killed git process groups haven't exited", groups.len());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Waits until processes of cancelled git commands exit, so their directories can be deleted
+/// without them still writing there
+pub async fn wait_killed() {
+    let groups = std::mem::take(&mut *KILLED_GROUPS.lock().unwrap());
+    wait_exited(groups).await;
+}
+
+/// Passwords of HTTP(S) remotes by `<scheme>://<user>@<host>`, `None` if the credential helper
+/// is disabled and credentials are embedded into remote URLs
+static CREDENTIALS: Mutex<Option<BTreeMap<String, String>>> = Mutex::new(None);
+
+const CREDENTIAL_ENV_PREFIX: &str = "GTLBSTR_GIT_CREDENTIAL_";
+/// A credential helper answering `get` with the password of the remote from the env
+/// of the git command, `<remote> <password>` values of the `GTLBSTR_GIT_CREDENTIAL_*` variables
+const CREDENTIAL_HELPER: &str = r#"!f() { test "$1" = get || return 0; while IFS='=' read -r k v; do case "$k" in protocol) p=$v;; host) h=$v;; username) u=$v;; esac; done; env | while IFS='=' read -r k v; do case "$k" in GTLBSTR_GIT_CREDENTIAL_*) if test "${v%% *}" = "$p://$u@$h"; then printf 'password=%s\n' "${v#* }"; break; fi;; esac; done; }; f"#;
+
+/// Enables (or disables) passing passwords of HTTP(S) remotes to git with a credential helper,
+/// so they aren't written to `.git/config` and aren't seen in arguments of git commands
+pub fn set_credential_helper(enabled: bool) {
+    *CREDENTIALS.lock().unwrap() = enabled.then(BTreeMap::new);
+}
+
+/// The URL of the HTTP(S) remote with the credentials (`<user>:<password>`).
+/// With the credential helper only the user is in the URL, the password is passed by the helper
+pub fn http_remote(url: &str, credentials: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        panic!("project with incorrect http path")
+    };
+    let mut helper = CREDENTIALS.lock().unwrap();
+    let Some(passwords) = helper.as_mut() else {
+        return format!("{}://{}@{}", scheme, credentials, rest);
+    };
+    let (user, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+    let host = rest.split('/').next().unwrap_or(rest);
+    passwords.insert(
+        format!("{}://{}@{}", scheme, user, host),
+        password.to_string(),
+    );
+    format!("{}://{}@{}", scheme, urlencoding::encode(user), rest)
+}
+
+/// Environment variables with secrets: config options with secrets,
+/// and the credential helper with the passwords
+fn secret_env() -> Vec<(String, String)> {
+    let mut config: Vec<(String, String)> =
+        SECRET_CONFIG.lock().unwrap().clone().into_iter().collect();
+    let mut env = vec![];
+    if let Some(passwords) = CREDENTIALS.lock().unwrap().as_ref() {
+        // other helpers of the user are reset, so they don't answer or store the passwords
+        config.push(("credential.helper".to_string(), String::new()));
+        config.push((
+            "credential.helper".to_string(),
+            CREDENTIAL_HELPER.to_string(),
+        ));
+        for (i, (remote, password)) in passwords.iter().enumerate() {
+            env.push((
+                format!("{}{}", CREDENTIAL_ENV_PREFIX, i),
+                format!("{} {}", remote, password),
+            ));
+        }
+    }
+    if !config.is_empty() {
+        env.push(("GIT_CONFIG_COUNT".to_string(), config.len().to_string()));
+    }
+    for (i, (key, value)) in config.into_iter().enumerate() {
+        env.push((format!("GIT_CONFIG_KEY_{}", i), key));
+        env.push((format!("GIT_CONFIG_VALUE_{}", i), value));
+    }
+    env
+}
+
+/// SSH options of a GitLab instance, so they don't depend on `~/.ssh/config`
+#[derive(Debug, Clone, Default)]
+pub struct SshOptions {
+    /// The private key, other identities aren't tried
+    pub key: Option<String>,
+    /// The port of the SSH server
+    pub port: Option<u16>,
+    /// Options passed with `-o` to ssh
+    pub options: Vec<String>,
+}
+
+impl SshOptions {
+    /// Arguments of ssh, the port is set in remote URLs
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(key) = &self.key {
+            args.extend(["-i".to_string(), key.clone()]);
+            args.extend(["-o".to_string(), "IdentitiesOnly=yes".to_string()]);
+        }
+        for option in &self.options {
+            args.extend(["-o".to_string(), option.clone()]);
+        }
+        args
+    }
+}
+
+/// Whether git fetches from an SSH remote or pushes to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SshRole {
+    Fetch,
+    Push,
+}
+
+impl SshRole {
+    fn as_str(self) -> &'static str {
+        match self {
+            SshRole::Fetch => "fetch",
+            SshRole::Push => "push",
+        }
+    }
+}
+
+/// Arguments of ssh by hosts, ports (empty without the port in the URL) and roles
+/// of SSH remotes, so fetch and backup remotes on the same host have their own keys
+static SSH_ARGS: Mutex<BTreeMap<(String, String, SshRole), Vec<String>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Arguments of ssh for all hosts, the first value of an option is used by ssh,
+/// so arguments of the host take precedence
+static SSH_COMMON_ARGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Sets the host key policy of ssh (`StrictHostKeyChecking`, the default of ssh with `None`)
+/// and the known hosts file (`~/.ssh/known_hosts` with `None`) for all hosts
+pub fn set_ssh_host_keys(checking: Option<&str>, known_hosts: Option<&str>) {
+    let mut args = vec![];
+    if let Some(checking) = checking {
+        args.extend([
+            "-o".to_string(),
+            format!("StrictHostKeyChecking={}", checking),
+        ]);
+    }
+    if let Some(known_hosts) = known_hosts {
+        args.extend([
+            "-o".to_string(),
+            format!("UserKnownHostsFile={}", known_hosts),
+        ]);
+    }
+    *SSH_COMMON_ARGS.lock().unwrap() = args;
+}
+
+const SSH_ENV_PREFIX: &str = "GTLBSTR_GIT_SSH_";
+/// Separates the host, the port, the role and arguments in values of the `GTLBSTR_GIT_SSH_*` variables
+const SSH_ENV_SEPARATOR: char = '\x1f';
+/// The ssh command of the user which the wrapper runs, `ssh` (or `core.sshCommand`) without it
+const SSH_USER_COMMAND_ENV: &str = "GTLBSTR_SSH_COMMAND";
+/// An ssh command finding the host and the port (`-p`) in arguments of git and the role
+/// in the remote command (`git-receive-pack` and LFS uploads push), and adding their arguments
+/// from the env of the git command, `<host>\x1f<port>\x1f<role>\x1f<arg>\x1f<arg>...` values
+/// of the `GTLBSTR_GIT_SSH_*` variables, arguments of all hosts are in `GTLBSTR_GIT_SSH`.
+/// The arguments are passed to the ssh command of the user
+const SSH_COMMAND: &str = r#"f() { d=$(printf '\037'); h=; p=; r=fetch; s=; for a in "$@"; do if test -n "$s"; then test "$s" = -p && p=$a; s=; elif test -z "$h"; then case "$a" in -[BbcDEeFIiJLlmOopQRSWw]) s=$a;; -*) ;; *) h=${a#*@};; esac; else case "$a" in git-receive-pack*|*" upload"|upload) r=push;; esac; fi; done; x="$h$d$p$d$r$d"; o=$(env | while IFS='=' read -r k v; do case "$k" in GTLBSTR_GIT_SSH_*) case "$v" in "$x"*) printf '%s' "${v#"$x"}"; break;; esac;; esac; done); c=${GTLBSTR_SSH_COMMAND:-$(git config core.sshCommand)}; set -f; IFS=$d; set -- $o $GTLBSTR_GIT_SSH "$@"; unset IFS; set +f; eval "exec ${c:-ssh}"' "$@"'; }; f"#;
+
+/// The user, the host, the port and the path of the SSH remote (`[<user>@]<host>:<path>` or
+/// `ssh://[<user>@]<host>[:<port>]/<path>`), IPv6 hosts in brackets are returned without them
+fn split_ssh_url(url: &str) -> Option<(&str, &str, Option<&str>, &str)> {
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let (authority, path) = rest.split_once('/')?;
+        let (user, host) = authority.rsplit_once('@').unwrap_or(("", authority));
+        let (host, port) = match host.strip_prefix('[') {
+            Some(host) => {
+                let (host, port) = host.split_once(']')?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match host.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host, None),
+            },
+        };
+        Some((user, host, port, path))
+    } else if url.contains("://") {
+        None
+    } else {
+        let (user, rest) = url
+            .split_once('@')
+            .filter(|(user, _)| !user.contains([':', '/']))
+            .unwrap_or(("", url));
+        let (host, path) = match rest.strip_prefix('[') {
+            Some(rest) => rest.split_once("]:")?,
+            None => rest.split_once(':')?,
+        };
+        Some((user, host, None, path))
+    }
+}
+
+/// The URL of the SSH remote with the port of the options,
+/// arguments of ssh are registered for the host, the port and the role of the remote
+pub fn ssh_remote(url: &str, ssh: &SshOptions, role: SshRole) -> String {
+    let Some((user, host, port, path)) = split_ssh_url(url) else {
+        return url.to_string();
+    };
+    let port = ssh.port.map(|p| p.to_string()).or(port.map(String::from));
+    let args = ssh.args();
+    if !args.is_empty() {
+        let key = (host.to_string(), port.clone().unwrap_or_default(), role);
+        SSH_ARGS.lock().unwrap().insert(key, args);
+    }
+    let Some(port) = port else {
+        return url.to_string();
+    };
+    let user = if user.is_empty() {
+        String::new()
+    } else {
+        format!("{}@", user)
+    };
+    let host = if host.contains(':') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    };
+    format!(
+        "ssh://{}{}:{}/{}",
+        user,
+        host,
+        port,
+        path.trim_start_matches('/')
+    )
+}
+
+/// Quotes the word for the shell
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// The ssh command the user set for git in the env (`core.sshCommand` is read by the wrapper)
+fn user_ssh_command() -> Option<String> {
+    let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+    var("GIT_SSH_COMMAND").or_else(|| var("GIT_SSH").map(|program| shell_quote(&program)))
+}
+
+/// Environment variables of the ssh command with arguments of the remotes, the ssh command
+/// of the user runs as it is if there are no arguments
+fn ssh_env() -> Vec<(String, String)> {
+    let hosts = SSH_ARGS.lock().unwrap();
+    let host_keys = SSH_COMMON_ARGS.lock().unwrap();
+    if hosts.is_empty() && host_keys.is_empty() {
+        return vec![];
+    }
+    // ssh doesn't ask for passphrases, passwords and trusting host keys, so workers don't hang
+    // on prompts, an option of the host can enable them again
+    let mut common = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+    common.extend(host_keys.iter().cloned());
+    let mut env = vec![("GIT_SSH_COMMAND".to_string(), SSH_COMMAND.to_string())];
+    // the variant isn't detected by the wrapper, the arguments are of OpenSSH
+    if std::env::var_os("GIT_SSH_VARIANT").is_none() {
+        env.push(("GIT_SSH_VARIANT".to_string(), "ssh".to_string()));
+    }
+    if let Some(command) = user_ssh_command() {
+        env.push((SSH_USER_COMMAND_ENV.to_string(), command));
+    }
+    for (i, ((host, port, role), args)) in hosts.iter().enumerate() {
+        let mut value = [host.as_str(), port, role.as_str()].join(&SSH_ENV_SEPARATOR.to_string());
+        for arg in args {
+            value.push(SSH_ENV_SEPARATOR);
+            value.push_str(arg);
+        }
+        env.push((format!("{}{}", SSH_ENV_PREFIX, i), value));
+    }
+    let separator = SSH_ENV_SEPARATOR.to_string();
+    env.push(("GTLBSTR_GIT_SSH".to_string(), common.join(&separator)));
+    env
+}
+
+/// Options of fetching repositories from the source
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Fetch only the last commits of the history
+    pub depth: Option<u32>,
+    /// Fetch only the history after the date
+    pub since: Option<NaiveDate>,
+    /// Fetch objects partially with the filter, e.g. `blob:none`
+    pub filter: Option<String>,
+    /// Fetch only branches matching any of the patterns, all branches if it's empty
+    pub branches: Vec<Regex>,
+    /// Fetch hidden GitLab refs ([`HIDDEN_REFS`])
+    pub hidden_refs: bool,
+    /// A local repository whose objects are borrowed with alternates instead of copying them
+    pub reference: Option<String>,
+}
+
+/// Namespaces of refs which GitLab creates for merge requests, pipelines and commits
+/// it keeps, ordinary clones don't fetch them
+pub const HIDDEN_REFS: [&str; 3] = ["merge-requests", "pipelines", "keep-around"];
+
+/// Hidden refs are pushed under the prefix, the backup GitLab denies pushes to their namespaces
+const BACKUP_HIDDEN_PREFIX: &str = "refs/gitlab/";
+
+impl FetchOptions {
+    /// Shallow clones don't have the whole history
+    pub fn is_shallow(&self) -> bool {
+        self.depth.is_some() || self.since.is_some()
+    }
+
+    /// Arguments of `clone`, `fetch` and `pull`
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(depth) = self.depth {
+            args.push(format!("--depth={}", depth));
+        }
+        if let Some(since) = self.since {
+            args.push(format!("--shallow-since={}", since));
+        }
+        if let Some(filter) = &self.filter {
+            args.push(format!("--filter={}", filter));
+        }
+        args
+    }
+
+    fn matches_branch(&self, branch: &str) -> bool {
+        self.branches.iter().any(|re| re.is_match(branch))
+    }
+}
+
+/// Hides the password of `<scheme>://<user>:<password>@` in the argument of a logged command
+fn redact_userinfo(arg: &str) -> String {
+    let Some(start) = arg.find("://").map(|i| i + 3) else {
+        return arg.to_string();
+    };
+    let authority_end = arg[start..]
+        .find(['/', '?', '#'])
+        .map_or(arg.len(), |i| start + i);
+    let Some(at) = arg[start..authority_end].rfind('@').map(|i| start + i) else {
+        return arg.to_string();
+    };
+    let user = arg[start..at].split(':').next().unwrap_or_default();
+    format!("{}{}:{}{}", &arg[..start], user, REDACTED, &arg[at..])
+}
 
 async fn git<S: AsRef<OsStr>>(args: Vec<S>) -> Result<String> {
-    let mut git_cmd = "git".to_string();
+    // config options of user arguments take precedence over `GIT_CONFIG_*` ones
+    let config = ARGS.lock().unwrap().clone();
+    let executable = EXECUTABLE
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| "git".to_string());
+
+    let mut git_cmd = executable.clone();
+    for c in &config {
+        git_cmd += &format!(" {}", redact_userinfo(c));
+    }
     for a in &args {
-        git_cmd += &format!(" {}", a.as_ref().to_str().unwrap());
+        git_cmd += &format!(" {}", redact_userinfo(a.as_ref().to_str().unwrap()));
     }
     info!("{}", git_cmd);
 
-    let cmd = Command::new("git").args(args).output().await?;
+    let env = ENV.lock().unwrap().clone();
+    let mut command = std::process::Command::new(executable);
+    #[cfg(unix)]
+    if OWN_PROCESS_GROUP.load(Ordering::Relaxed) {
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    }
+    let child = Command::from(command)
+        .args(config)
+        .args(args)
+        // git doesn't ask for credentials of HTTP(S) remotes in the terminal
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .envs(env)
+        .envs(secret_env())
+        .envs(ssh_env())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let own_group = cfg!(unix) && OWN_PROCESS_GROUP.load(Ordering::Relaxed);
+    let mut group = ProcessGroup(child.id().filter(|_| own_group));
+    let output = child.wait_with_output();
+    let timeout = *TIMEOUT.lock().unwrap();
+    let cmd = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, output).await {
+            Ok(cmd) => cmd,
+            Err(_) => {
+                // the partial clone is removed after the error, its writers have to exit first
+                group.kill().await;
+                return Err(Error::Timeout(format!(
+                    "git didn't finish in {:?}",
+                    timeout
+                )));
+            }
+        },
+        None => output.await,
+    };
+    group.exited();
+    let cmd = cmd?;
 
     let errmsg = from_utf8(&cmd.stderr).unwrap_or_default();
     if !cmd.status.success() {
         warn!("git exit status not success");
-        bail!("git error: {}", errmsg);
+        return Err(match prompt_hint(errmsg) {
+            Some(hint) => Error::Prompt(format!("{}: {}", hint, errmsg)),
+            None => Error::Git(errmsg.to_string()),
+        });
     }
 
     if !errmsg.is_empty() {
         info!("{}", errmsg);
     }
 
-    Ok(from_utf8(&cmd.stdout)?.to_string())
+    let out = from_utf8(&cmd.stdout).map_err(|e| Error::Git(e.to_string()))?;
+    Ok(out.to_string())
+}
+
+/// Explains failures of git and ssh which would ask the user in an interactive run
+fn prompt_hint(stderr: &str) -> Option<&'static str> {
+    if stderr.contains("Host key verification failed") {
+        Some("the ssh host key isn't known and isn't trusted without a prompt, set the host key policy or add the key to known hosts")
+    } else if stderr.contains("Permission denied (publickey") {
+        Some("ssh authentication failed, keys with a passphrase are used only through an ssh agent without a prompt")
+    } else if stderr.contains("terminal prompts disabled") {
+        Some("git asked for credentials, prompts are disabled")
+    } else {
+        None
+    }
 }
 
-async fn check_status(path: &String) -> Result<()> {
+pub async fn check_status(path: &str) -> Result<()> {
     git(vec!["-C", path, "rev-parse", "--is-inside-work-tree"])
         .await
         .map(|_| ())
 }
 
-async fn clone(src: &String, dst: &String) -> Result<()> {
-    git(vec!["clone", src, dst]).await?;
+/// Runs the git command with the options inserted after the fixed arguments
+async fn git_with(args: &[&str], options: &FetchOptions, rest: &[&str]) -> Result<String> {
+    let mut all: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    all.extend(options.args());
+    all.extend(rest.iter().map(|a| a.to_string()));
+    git(all).await
+}
+
+async fn clone(src: &str, dst: &str, options: &FetchOptions) -> Result<()> {
+    let mut args = vec!["clone"];
+    // shallow clones fetch only the default branch by default
+    if options.is_shallow() {
+        args.push("--no-single-branch");
+    }
+    if let Some(reference) = &options.reference {
+        args.extend(["--reference", reference]);
+    }
+    git_with(&args, options, &[src, dst]).await?;
+    git(vec!["-C", dst, "remote", "rename", "origin", "upstream"]).await?;
+
+    Ok(())
+}
+
+/// Branches of the upstream remote matching the patterns and its default branch
+async fn matching_branches(path: &str, options: &FetchOptions) -> Result<(Vec<String>, String)> {
+    let out = git(vec![
+        "-C",
+        path,
+        "ls-remote",
+        "--symref",
+        "upstream",
+        "HEAD",
+        "refs/heads/*",
+    ])
+    .await?;
+    let mut branches = vec![];
+    let mut head = String::new();
+    for line in out.lines() {
+        if let Some((target, _)) = line
+            .strip_prefix("ref: refs/heads/")
+            .and_then(|l| l.split_once('\t'))
+        {
+            head = target.to_string();
+        } else if let Some((_, branch)) = line.split_once("\trefs/heads/") {
+            if options.matches_branch(branch) {
+                branches.push(branch.to_string());
+            }
+        }
+    }
+    if branches.is_empty() {
+        return Err(Error::Git("no branches match the patterns".to_string()));
+    }
+    Ok((branches, head))
+}
+
+/// Fetches only the matching branches of the upstream remote into `prefix`, refs of other
+/// branches are removed from it. Returns the branch to check out: the default one if
+/// it matches, otherwise the first matching one
+async fn fetch_branches(path: &str, options: &FetchOptions, prefix: &str) -> Result<String> {
+    let (branches, head) = matching_branches(path, options).await?;
+    let refspecs: Vec<String> = branches
+        .iter()
+        .map(|b| format!("+refs/heads/{}:{}{}", b, prefix, b))
+        .collect();
+    let mut rest = vec!["upstream"];
+    rest.extend(refspecs.iter().map(String::as_str));
+    git_with(&["-C", path, "fetch"], options, &rest).await?;
+
+    let out = git(vec![
+        "-C",
+        path,
+        "for-each-ref",
+        "--format=%(refname)",
+        prefix,
+    ])
+    .await?;
+    for stale in out
+        .lines()
+        .filter(|r| !branches.iter().any(|b| r.strip_prefix(prefix) == Some(b)))
+    {
+        git(vec!["-C", path, "update-ref", "-d", stale]).await?;
+    }
+
+    Ok(match branches.contains(&head) {
+        true => head,
+        false => branches[0].clone(),
+    })
+}
+
+/// Clones only the matching branches
+async fn clone_branches(src: &str, dst: &str, options: &FetchOptions) -> Result<()> {
+    git(vec!["init", "-q", dst]).await?;
+    git(vec!["-C", dst, "remote", "add", "upstream", src]).await?;
+    if let Some(reference) = &options.reference {
+        borrow_objects(dst, reference).await?;
+    }
+    let branch = fetch_branches(dst, options, "refs/remotes/upstream/").await?;
+    let remote_branch = format!("upstream/{}", branch);
+    git(vec![
+        "-C",
+        dst,
+        "checkout",
+        "-q",
+        "-b",
+        &branch,
+        "--track",
+        &remote_branch,
+    ])
+    .await?;
+    Ok(())
+}
+
+/// Mirrors only the matching branches and tags pointing into their history
+async fn update_mirror_branches(path: &str, options: &FetchOptions) -> Result<()> {
+    let head = format!(
+        "refs/heads/{}",
+        fetch_branches(path, options, "refs/heads/").await?
+    );
+    git(vec!["-C", path, "symbolic-ref", "HEAD", &head]).await?;
+    Ok(())
+}
+
+async fn clone_mirror_branches(src: &str, dst: &str, options: &FetchOptions) -> Result<()> {
+    git(vec!["init", "-q", "--bare", dst]).await?;
+    git(vec![
+        "-C",
+        dst,
+        "remote",
+        "add",
+        "--mirror=fetch",
+        "upstream",
+        src,
+    ])
+    .await?;
+    if let Some(reference) = &options.reference {
+        borrow_objects(dst, reference).await?;
+    }
+    update_mirror_branches(dst, options).await
+}
+
+async fn clone_mirror(src: &str, dst: &str, options: &FetchOptions) -> Result<()> {
+    let mut args = vec!["clone", "--mirror"];
+    if let Some(reference) = &options.reference {
+        args.extend(["--reference", reference]);
+    }
+    git_with(&args, options, &[src, dst]).await?;
     git(vec!["-C", dst, "remote", "rename", "origin", "upstream"]).await?;
 
     Ok(())
 }
 
-async fn update(path: &String, only_master: bool) -> Result<()> {
+async fn update_mirror(path: &str, options: &FetchOptions) -> Result<()> {
+    if !options.branches.is_empty() {
+        return update_mirror_branches(path, options).await;
+    }
+    git_with(&["-C", path, "fetch", "--prune"], options, &["upstream"]).await?;
+    Ok(())
+}
+
+async fn update(path: &str, only_master: bool, options: &FetchOptions) -> Result<()> {
+    // pull doesn't take the filter, the filter of the clone is saved in its config
+    let pull_options = FetchOptions {
+        filter: None,
+        ..options.clone()
+    };
     if only_master {
-        git(vec!["-C", path, "pull"]).await?;
+        git_with(&["-C", path, "pull"], &pull_options, &[]).await?;
         return Ok(());
     }
 
-    git(vec!["-C", path, "fetch", "--all"]).await?;
+    if options.branches.is_empty() {
+        git_with(&["-C", path, "fetch", "--all"], options, &[]).await?;
+    } else {
+        let branch = fetch_branches(path, options, "refs/remotes/upstream/").await?;
+        // the checked out branch doesn't match the patterns anymore
+        let current = git(vec!["-C", path, "branch", "--show-current"]).await?;
+        if !options.matches_branch(current.trim()) {
+            let remote_branch = format!("upstream/{}", branch);
+            git(vec![
+                "-C",
+                path,
+                "checkout",
+                "-q",
+                "-B",
+                &branch,
+                "--track",
+                &remote_branch,
+            ])
+            .await?;
+        }
+    }
 
     let branches_out = git(vec!["-C", path, "branch", "-la"]).await?;
     let branches = branches_out
         .split('\n')
-        .into_iter()
         .map(|v| v.trim())
         .filter(|v| !v.is_empty())
         .filter(|v| !v.starts_with("remotes/upstream/HEAD"))
@@ -85,36 +788,395 @@ async fn update(path: &String, only_master: bool) -> Result<()> {
         }
     }
 
-    git(vec!["-C", path, "pull", "upstream", default_branch]).await?;
+    git_with(
+        &["-C", path, "pull"],
+        &pull_options,
+        &["upstream", default_branch],
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Fetches hidden refs of the upstream remote into the same namespaces
+async fn fetch_hidden_refs(path: &str, options: &FetchOptions) -> Result<()> {
+    let refspecs: Vec<String> = HIDDEN_REFS
+        .iter()
+        .map(|ns| format!("+refs/{}/*:refs/{}/*", ns, ns))
+        .collect();
+    let mut rest = vec!["upstream"];
+    rest.extend(refspecs.iter().map(String::as_str));
+    git_with(&["-C", path, "fetch"], options, &rest).await?;
+    Ok(())
+}
+
+/// The alternates file of the repository
+async fn alternates_file(path: &str) -> Result<PathBuf> {
+    let out = git(vec![
+        "-C",
+        path,
+        "rev-parse",
+        "--git-path",
+        "objects/info/alternates",
+    ])
+    .await?;
+    Ok(Path::new(path).join(out.trim()))
+}
+
+/// Object directories of other repositories which the repository borrows objects from
+pub async fn alternates(path: &str) -> Result<Vec<String>> {
+    match std::fs::read_to_string(alternates_file(path).await?) {
+        Ok(content) => Ok(content.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Makes the repository borrow objects of the reference repository, objects which
+/// the reference has are removed from the repository. Unreachable objects of the reference
+/// aren't pruned anymore, since the repository may still use them
+async fn borrow_objects(path: &str, reference: &str) -> Result<()> {
+    let objects = git(vec![
+        "-C",
+        reference,
+        "rev-parse",
+        "--path-format=absolute",
+        "--git-path",
+        "objects",
+    ])
+    .await?;
+    git(vec!["-C", reference, "config", "gc.pruneExpire", "never"]).await?;
+    let objects = objects.trim().to_string();
+    let current = alternates(path).await?;
+    if current.contains(&objects) {
+        return Ok(());
+    }
+    std::fs::write(alternates_file(path).await?, objects + "\n")?;
+    git(vec!["-C", path, "repack", "-a", "-d", "-l", "-q"]).await?;
+    Ok(())
+}
 
+/// Copies borrowed objects into the repository and removes its alternates,
+/// so it doesn't depend on other repositories anymore
+pub async fn dissociate(path: &str) -> Result<()> {
+    if alternates(path).await?.is_empty() {
+        return Ok(());
+    }
+    git(vec!["-C", path, "repack", "-a", "-d", "-q"]).await?;
+    std::fs::remove_file(alternates_file(path).await?)?;
     Ok(())
 }
 
-async fn add_remote_backup(path: &String, remote: String) -> Result<()> {
+async fn add_remote_backup(path: &str, remote: String) -> Result<()> {
     let _ = git(vec!["-C", path, "remote", "remove", "backup"]).await;
     git(vec!["-C", path, "remote", "add", "backup", &remote]).await?;
     Ok(())
 }
 
-async fn push_all_remote_backup(path: String) -> Result<()> {
-    if let Err(e) = git(vec!["-C", &path, "push", "-u", "backup", "--all"]).await {
-        error!("{}", e)
-    };
-    if let Err(e) = git(vec!["-C", &path, "push", "-u", "backup", "--tags"]).await {
-        error!("{}", e)
-    };
+async fn push_all_remote_backup(path: String, hidden_refs: bool) -> Result<()> {
+    git(vec!["-C", &path, "push", "-u", "backup", "--all"]).await?;
+    git(vec!["-C", &path, "push", "-u", "backup", "--tags"]).await?;
+    if hidden_refs {
+        let refspecs: Vec<String> = HIDDEN_REFS
+            .iter()
+            .map(|ns| format!("+refs/{}/*:{}{}/*", ns, BACKUP_HIDDEN_PREFIX, ns))
+            .collect();
+        let mut args = vec!["-C", &path, "push", "backup"];
+        args.extend(refspecs.iter().map(String::as_str));
+        git(args).await?;
+    }
     Ok(())
 }
 
-pub async fn fetch(src: String, dst: String, only_master: bool) -> Result<()> {
-    match check_status(&dst).await {
-        Ok(_) => (),
-        Err(_) => clone(&src, &dst).await?,
-    };
-    update(&dst, only_master).await
+/// Removes the partial clone left by a failed (e.g. killed by the timeout) clone,
+/// unless the directory existed before it
+fn cleanup_failed_clone<T>(result: Result<T>, dst: &str, existed: bool) -> Result<T> {
+    if result.is_err() && !existed {
+        let _ = std::fs::remove_dir_all(dst);
+    }
+    result
+}
+
+/// Points the upstream remote of an existing clone to the URL, so credentials saved
+/// to its config by previous runs are replaced
+async fn set_upstream(path: &str, src: &str) -> Result<()> {
+    git(vec!["-C", path, "remote", "set-url", "upstream", src]).await?;
+    Ok(())
+}
+
+pub async fn fetch(
+    src: String,
+    dst: String,
+    only_master: bool,
+    options: &FetchOptions,
+) -> Result<()> {
+    if check_status(&dst).await.is_err() {
+        let existed = Path::new(&dst).exists();
+        let cloned = match options.branches.is_empty() {
+            true => clone(&src, &dst, options).await,
+            false => clone_branches(&src, &dst, options).await,
+        };
+        cleanup_failed_clone(cloned, &dst, existed)?;
+    } else {
+        set_upstream(&dst, &src).await?;
+    }
+    if let Some(reference) = &options.reference {
+        borrow_objects(&dst, reference).await?;
+    }
+    update(&dst, only_master, options).await?;
+    if options.hidden_refs {
+        fetch_hidden_refs(&dst, options).await?;
+    }
+    Ok(())
+}
+
+pub async fn fetch_mirror(src: String, dst: String, options: &FetchOptions) -> Result<()> {
+    let new = check_status(&dst).await.is_err();
+    if new {
+        let existed = Path::new(&dst).exists();
+        let cloned = match options.branches.is_empty() {
+            true => clone_mirror(&src, &dst, options).await,
+            false => clone_mirror_branches(&src, &dst, options).await,
+        };
+        cleanup_failed_clone(cloned, &dst, existed)?;
+    } else {
+        set_upstream(&dst, &src).await?;
+    }
+    if let Some(reference) = &options.reference {
+        borrow_objects(&dst, reference).await?;
+    }
+    // the filtered mirror is already fetched by the clone
+    if !new || options.branches.is_empty() {
+        update_mirror(&dst, options).await?;
+    }
+    if options.hidden_refs {
+        fetch_hidden_refs(&dst, options).await?;
+    }
+    Ok(())
+}
+
+/// Returns object ids of all local branches and tags
+pub async fn tips(path: &str) -> Result<Vec<String>> {
+    let out = git(vec![
+        "-C",
+        path,
+        "for-each-ref",
+        "--format=%(objectname)",
+        "refs/heads",
+        "refs/tags",
+    ])
+    .await?;
+    Ok(out.lines().map(str::to_string).collect())
 }
 
-pub async fn push_backup(path: String, remote: String) -> Result<()> {
+/// Returns object ids of all local branches and tags by ref names
+pub async fn refs(path: &str) -> Result<BTreeMap<String, String>> {
+    let out = git(vec![
+        "-C",
+        path,
+        "for-each-ref",
+        "--format=%(refname) %(objectname)",
+        "refs/heads",
+        "refs/tags",
+    ])
+    .await?;
+    Ok(out
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .map(|(name, oid)| (name.to_string(), oid.to_string()))
+        .collect())
+}
+
+/// Creates a bundle with HEAD, all local branches and tags,
+/// objects reachable from `exclude` aren't included in it
+pub async fn bundle(path: &str, file: &str, exclude: &[String]) -> Result<()> {
+    let mut args = vec![
+        "-C",
+        path,
+        "bundle",
+        "create",
+        file,
+        "HEAD",
+        "--branches",
+        "--tags",
+    ];
+    if !exclude.is_empty() {
+        args.push("--not");
+        args.extend(exclude.iter().map(String::as_str));
+    }
+    git(args).await.map(|_| ())
+}
+
+/// Pushes all local branches and tags to the remote url
+pub async fn push_all(path: &str, remote: &str) -> Result<()> {
+    git(vec!["-C", path, "push", remote, "--all"]).await?;
+    git(vec!["-C", path, "push", remote, "--tags"]).await?;
+    Ok(())
+}
+
+/// Compacts the repository with `git gc`
+pub async fn gc(path: &str, aggressive: bool) -> Result<()> {
+    let mut args = vec!["-C", path, "gc", "--quiet"];
+    if aggressive {
+        args.push("--aggressive");
+    }
+    git(args).await.map(|_| ())
+}
+
+/// Checks that git-lfs is installed
+pub async fn lfs_installed() -> bool {
+    git(vec!["lfs", "version"]).await.is_ok()
+}
+
+/// Checks that the repository uses LFS (a `.gitattributes` at any level of HEAD has LFS filters)
+pub async fn uses_lfs(path: &str) -> bool {
+    git(vec![
+        "-C",
+        path,
+        "grep",
+        "-q",
+        "-F",
+        "filter=lfs",
+        "HEAD",
+        "--",
+        ":(glob)**/.gitattributes",
+    ])
+    .await
+    .is_ok()
+}
+
+/// Fetches LFS objects of all refs from the upstream remote
+pub async fn lfs_fetch(path: &str) -> Result<()> {
+    git(vec!["-C", path, "lfs", "fetch", "--all", "upstream"])
+        .await
+        .map(|_| ())
+}
+
+/// Pushes LFS objects of all refs to the backup remote
+pub async fn lfs_push(path: &str) -> Result<()> {
+    git(vec!["-C", path, "lfs", "push", "--all", "backup"])
+        .await
+        .map(|_| ())
+}
+
+/// Pushes all local branches and tags to the backup remote, and hidden refs
+/// under `refs/gitlab/` with `hidden_refs`
+pub async fn push_backup(path: String, remote: String, hidden_refs: bool) -> Result<()> {
     add_remote_backup(&path, remote).await?;
-    push_all_remote_backup(path).await
+    push_all_remote_backup(path, hidden_refs).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_scp_like_urls() {
+        assert_eq!(
+            split_ssh_url("git@gitlab.com:grp/p1.git"),
+            Some(("git", "gitlab.com", None, "grp/p1.git"))
+        );
+        assert_eq!(
+            split_ssh_url("gitlab.com:grp/p1.git"),
+            Some(("", "gitlab.com", None, "grp/p1.git"))
+        );
+        assert_eq!(
+            split_ssh_url("git@[::1]:grp/p1.git"),
+            Some(("git", "::1", None, "grp/p1.git"))
+        );
+    }
+
+    #[test]
+    fn split_ssh_scheme_urls() {
+        assert_eq!(
+            split_ssh_url("ssh://git@gitlab.com:2222/grp/p1.git"),
+            Some(("git", "gitlab.com", Some("2222"), "grp/p1.git"))
+        );
+        assert_eq!(
+            split_ssh_url("ssh://gitlab.com/grp/p1.git"),
+            Some(("", "gitlab.com", None, "grp/p1.git"))
+        );
+        assert_eq!(
+            split_ssh_url("ssh://git@[2001:db8::1]:2222/grp/p1.git"),
+            Some(("git", "2001:db8::1", Some("2222"), "grp/p1.git"))
+        );
+        assert_eq!(
+            split_ssh_url("ssh://[2001:db8::1]/grp/p1.git"),
+            Some(("", "2001:db8::1", None, "grp/p1.git"))
+        );
+    }
+
+    #[test]
+    fn split_other_urls() {
+        assert_eq!(split_ssh_url("https://gitlab.com/grp/p1.git"), None);
+        assert_eq!(split_ssh_url("/srv/git/p1.git"), None);
+    }
+
+    #[test]
+    fn ssh_remote_sets_port() {
+        let ssh = SshOptions {
+            port: Some(2222),
+            ..Default::default()
+        };
+        assert_eq!(
+            ssh_remote("git@gitlab.com:grp/p1.git", &ssh, SshRole::Fetch),
+            "ssh://git@gitlab.com:2222/grp/p1.git"
+        );
+        assert_eq!(
+            ssh_remote("git@[::1]:grp/p1.git", &ssh, SshRole::Fetch),
+            "ssh://git@[::1]:2222/grp/p1.git"
+        );
+        assert_eq!(
+            ssh_remote(
+                "git@gitlab.com:grp/p1.git",
+                &SshOptions::default(),
+                SshRole::Fetch
+            ),
+            "git@gitlab.com:grp/p1.git"
+        );
+    }
+
+    #[tokio::test]
+    async fn uses_lfs_of_nested_attributes() {
+        let dir = std::env::temp_dir().join(format!("gitlobster-lfs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("assets/img")).unwrap();
+        let path = dir.to_str().unwrap();
+        let commit = |message: &str| {
+            for args in [
+                vec!["add", "-A"],
+                vec![
+                    "-c",
+                    "user.name=t",
+                    "-c",
+                    "user.email=t@t",
+                    "commit",
+                    "-qm",
+                    message,
+                ],
+            ] {
+                let status = std::process::Command::new("git")
+                    .arg("-C")
+                    .arg(path)
+                    .args(args)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            }
+        };
+        git(vec!["init", "-q", path]).await.unwrap();
+        std::fs::write(dir.join(".gitattributes"), "*.sh text eol=lf\n").unwrap();
+        std::fs::write(dir.join("notes.gitattributes"), "*.png filter=lfs\n").unwrap();
+        commit("text");
+        assert!(!uses_lfs(path).await);
+
+        std::fs::write(
+            dir.join("assets/img/.gitattributes"),
+            "*.png filter=lfs diff=lfs merge=lfs -text\n",
+        )
+        .unwrap();
+        commit("lfs");
+        assert!(uses_lfs(path).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
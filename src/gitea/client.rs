@@ -0,0 +1,246 @@
+use crate::backup::PATH_SEPARATOR;
+use crate::error::Result;
+use crate::gitea::types;
+use crate::gitlab;
+use crate::http::secret_header;
+use crate::limiter::RateLimiter;
+use crate::retry::Retry;
+use reqwest::{Method, RequestBuilder, Response};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::info;
+use url::Url;
+
+const API_VERSION: &str = "v1";
+
+pub struct Client {
+    url: Url,
+    http: reqwest::Client,
+    disable_sync_date: bool,
+    token: String,
+    retry: Retry,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Client {
+    pub fn new(token: &str, mut url: Url, disable_sync_date: bool) -> Result<Self> {
+        let http = reqwest::Client::new();
+        let token = token.to_string();
+
+        url.set_path(&format!("api/{}", API_VERSION));
+
+        Ok(Client {
+            url,
+            http,
+            disable_sync_date,
+            token,
+            retry: Retry::NONE,
+            limiter: None,
+        })
+    }
+
+    /// Retries failed requests with the policy
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends requests with the HTTP client, it can be shared with other clients
+    pub fn with_http(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Limits the rate of requests with the limiter, it can be shared with other clients
+    pub fn with_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Sends the request with retries when the rate limit allows it
+    async fn send(&self, req: RequestBuilder) -> reqwest::Result<Response> {
+        self.retry.send(req, self.limiter.as_deref()).await
+    }
+
+    fn build_request<S: Into<String>, J: Serialize>(
+        &self,
+        m: Method,
+        path: S,
+        json: Option<J>,
+    ) -> RequestBuilder {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}/{}", url.path(), path.into()));
+
+        info!("{}", url);
+
+        let req = self
+            .http
+            .request(m, url)
+            .header("Content-Type", "application/json");
+        let mut req = secret_header(req, "Authorization", &format!("token {}", self.token));
+
+        if let Some(json) = json {
+            req = req.json(&json)
+        }
+
+        req
+    }
+
+    async fn request<S: Into<String>, J: Serialize>(
+        &self,
+        m: Method,
+        path: S,
+        json: Option<J>,
+    ) -> reqwest::Result<Response> {
+        self.send(self.build_request(m, path, json))
+            .await?
+            .error_for_status()
+    }
+
+    fn exist<T>(&self, resp: reqwest::Result<T>) -> reqwest::Result<Option<T>> {
+        match resp {
+            Ok(r) => Ok(Some(r)),
+            Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_current_user(&self) -> reqwest::Result<types::User> {
+        self.request(Method::GET, "user", None::<()>)
+            .await?
+            .json::<types::User>()
+            .await
+    }
+
+    pub async fn get_org(&self, name: &str) -> reqwest::Result<types::Organization> {
+        self.request(Method::GET, format!("orgs/{}", name), None::<()>)
+            .await?
+            .json::<types::Organization>()
+            .await
+    }
+
+    pub async fn org_exist(&self, name: &str) -> reqwest::Result<Option<types::Organization>> {
+        self.exist(self.get_org(name).await)
+    }
+
+    pub async fn make_org(&self, name: &str) -> reqwest::Result<types::Organization> {
+        #[derive(Serialize)]
+        struct MakeOrgRequest<'a> {
+            username: &'a str,
+            visibility: &'a str,
+        }
+
+        let data = &MakeOrgRequest {
+            username: name,
+            visibility: "private",
+        };
+        self.request(Method::POST, "orgs", Some(data))
+            .await?
+            .json::<types::Organization>()
+            .await
+    }
+
+    pub async fn get_repo(&self, owner: &str, name: &str) -> reqwest::Result<types::Repository> {
+        self.request(Method::GET, format!("repos/{}/{}", owner, name), None::<()>)
+            .await?
+            .json::<types::Repository>()
+            .await
+    }
+
+    pub async fn repo_exist(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> reqwest::Result<Option<types::Repository>> {
+        self.exist(self.get_repo(owner, name).await)
+    }
+
+    pub async fn make_repo(
+        &self,
+        name: String,
+        org: Option<&str>,
+        info: &gitlab::types::Project,
+    ) -> reqwest::Result<types::Repository> {
+        #[derive(Serialize)]
+        struct MakeRepoRequest {
+            name: String,
+            description: String,
+            private: bool,
+        }
+
+        let description = gitlab::client::make_project_description(
+            info.description.clone(),
+            self.disable_sync_date,
+        );
+        let data = &MakeRepoRequest {
+            name,
+            description,
+            private: true,
+        };
+
+        let path = match org {
+            Some(org) => format!("orgs/{}/repos", org),
+            None => "user/repos".to_string(),
+        };
+        self.request(Method::POST, path, Some(data))
+            .await?
+            .json::<types::Repository>()
+            .await
+    }
+
+    pub async fn update_repo(
+        &self,
+        repo: &types::Repository,
+        info: &gitlab::types::Project,
+    ) -> reqwest::Result<types::Repository> {
+        #[derive(Serialize)]
+        struct UpdateRepoRequest {
+            description: String,
+        }
+
+        let description = gitlab::client::make_project_description(
+            info.description.clone(),
+            self.disable_sync_date,
+        );
+        let data = &UpdateRepoRequest { description };
+
+        self.request(
+            Method::PATCH,
+            format!("repos/{}", repo.full_name),
+            Some(data),
+        )
+        .await?
+        .json::<types::Repository>()
+        .await
+    }
+
+    /// Creates or updates a repository for the project. Organizations in Gitea can't be nested,
+    /// so if the target org isn't passed, the root group of the project becomes an organization
+    /// and the rest of the path becomes the repository name
+    pub async fn make_repo_with_namespace(
+        &self,
+        mut path: Vec<String>,
+        org: &Option<String>,
+        login: &str,
+        project_info: &gitlab::types::Project,
+    ) -> reqwest::Result<types::Repository> {
+        let org = match org {
+            Some(org) => Some(org.clone()),
+            None if path.len() > 1 => {
+                let root = path.remove(0);
+                if self.org_exist(&root).await?.is_none() {
+                    self.make_org(&root).await?;
+                }
+                Some(root)
+            }
+            None => None,
+        };
+        let name = path.join(PATH_SEPARATOR);
+        let owner = org.as_deref().unwrap_or(login);
+
+        match self.repo_exist(owner, &name).await? {
+            Some(r) => self.update_repo(&r, project_info).await,
+            None => self.make_repo(name, org.as_deref(), project_info).await,
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// Basic user information.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    /// The ID of the user.
+    pub id: u64,
+    /// The username.
+    pub login: String,
+}
+
+/// Organization information.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Organization {
+    /// The ID of the organization.
+    pub id: u64,
+    /// The name of the organization.
+    pub username: String,
+}
+
+/// Repository information.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Repository {
+    /// The ID of the repository.
+    pub id: u64,
+    /// The name of the repository.
+    pub name: String,
+    /// The name of the repository with its owner.
+    pub full_name: String,
+    /// The description of the repository.
+    pub description: Option<String>,
+    /// Whether the repository is private or not.
+    pub private: bool,
+    /// The URL to clone the repository over HTTPS.
+    pub clone_url: String,
+    /// The URL to clone the repository over SSH.
+    pub ssh_url: String,
+    /// The URL for the repository's homepage.
+    pub html_url: String,
+}
@@ -0,0 +1,209 @@
+use crate::backup::PATH_SEPARATOR;
+use crate::error::Result;
+use crate::github::types;
+use crate::gitlab;
+use crate::limiter::RateLimiter;
+use crate::retry::Retry;
+use reqwest::{Method, RequestBuilder, Response};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::info;
+use url::Url;
+
+const PUBLIC_HOST: &str = "github.com";
+const PUBLIC_API_URL: &str = "https://api.github.com/";
+const USER_AGENT: &str = concat!("gitlobster/", env!("CARGO_PKG_VERSION"));
+
+pub struct Client {
+    url: Url,
+    http: reqwest::Client,
+    disable_sync_date: bool,
+    token: String,
+    retry: Retry,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Client {
+    pub fn new(token: &str, url: Url, disable_sync_date: bool) -> Result<Self> {
+        let http = reqwest::Client::new();
+        let token = token.to_string();
+
+        // github.com has a separate API host, GitHub Enterprise serves the API by the path
+        let url = if url.host_str() == Some(PUBLIC_HOST) {
+            Url::parse(PUBLIC_API_URL)?
+        } else {
+            url.join("api/v3/")?
+        };
+
+        Ok(Client {
+            url,
+            http,
+            disable_sync_date,
+            token,
+            retry: Retry::NONE,
+            limiter: None,
+        })
+    }
+
+    /// Retries failed requests with the policy
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends requests with the HTTP client, it can be shared with other clients
+    pub fn with_http(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Limits the rate of requests with the limiter, it can be shared with other clients
+    pub fn with_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Sends the request with retries when the rate limit allows it
+    async fn send(&self, req: RequestBuilder) -> reqwest::Result<Response> {
+        self.retry.send(req, self.limiter.as_deref()).await
+    }
+
+    fn build_request<S: Into<String>, J: Serialize>(
+        &self,
+        m: Method,
+        path: S,
+        json: Option<J>,
+    ) -> RequestBuilder {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}{}", url.path(), path.into()));
+
+        info!("{}", url);
+
+        let mut req = self
+            .http
+            .request(m, url)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", USER_AGENT)
+            .bearer_auth(&self.token);
+
+        if let Some(json) = json {
+            req = req.json(&json)
+        }
+
+        req
+    }
+
+    async fn request<S: Into<String>, J: Serialize>(
+        &self,
+        m: Method,
+        path: S,
+        json: Option<J>,
+    ) -> reqwest::Result<Response> {
+        self.send(self.build_request(m, path, json))
+            .await?
+            .error_for_status()
+    }
+
+    pub async fn get_current_user(&self) -> reqwest::Result<types::User> {
+        self.request(Method::GET, "user", None::<()>)
+            .await?
+            .json::<types::User>()
+            .await
+    }
+
+    pub async fn get_repo(&self, owner: &str, name: &str) -> reqwest::Result<types::Repository> {
+        self.request(Method::GET, format!("repos/{}/{}", owner, name), None::<()>)
+            .await?
+            .json::<types::Repository>()
+            .await
+    }
+
+    pub async fn repo_exist(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> reqwest::Result<Option<types::Repository>> {
+        match self.get_repo(owner, name).await {
+            Ok(r) => Ok(Some(r)),
+            Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn make_repo(
+        &self,
+        name: String,
+        org: &Option<String>,
+        info: &gitlab::types::Project,
+    ) -> reqwest::Result<types::Repository> {
+        #[derive(Serialize)]
+        struct MakeRepoRequest {
+            name: String,
+            description: String,
+            private: bool,
+        }
+
+        let description = gitlab::client::make_project_description(
+            info.description.clone(),
+            self.disable_sync_date,
+        );
+        let data = &MakeRepoRequest {
+            name,
+            description,
+            private: true,
+        };
+
+        let path = match org {
+            Some(org) => format!("orgs/{}/repos", org),
+            None => "user/repos".to_string(),
+        };
+        self.request(Method::POST, path, Some(data))
+            .await?
+            .json::<types::Repository>()
+            .await
+    }
+
+    pub async fn update_repo(
+        &self,
+        repo: &types::Repository,
+        info: &gitlab::types::Project,
+    ) -> reqwest::Result<types::Repository> {
+        #[derive(Serialize)]
+        struct UpdateRepoRequest {
+            description: String,
+        }
+
+        let description = gitlab::client::make_project_description(
+            info.description.clone(),
+            self.disable_sync_date,
+        );
+        let data = &UpdateRepoRequest { description };
+
+        self.request(
+            Method::PATCH,
+            format!("repos/{}", repo.full_name),
+            Some(data),
+        )
+        .await?
+        .json::<types::Repository>()
+        .await
+    }
+
+    /// Creates or updates a repository with the name made of the project path,
+    /// the repository is owned by the org if it's passed or by the current user
+    pub async fn make_repo_with_namespace(
+        &self,
+        path: Vec<String>,
+        org: &Option<String>,
+        login: &str,
+        project_info: &gitlab::types::Project,
+    ) -> reqwest::Result<types::Repository> {
+        let name = path.join(PATH_SEPARATOR);
+        let owner = org.as_deref().unwrap_or(login);
+
+        match self.repo_exist(owner, &name).await? {
+            Some(r) => self.update_repo(&r, project_info).await,
+            None => self.make_repo(name, org, project_info).await,
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub use client::Client;
+
+pub mod client;
+
+pub mod types;
@@ -1,31 +1,145 @@
-use crate::gitlab::types;
-use anyhow::Result;
-use chrono::Utc;
-use reqwest::{Method, RequestBuilder, Response};
+use crate::error::{Error, Result};
+use crate::gitlab::version::{self, Version};
+use crate::gitlab::{graphql, oauth, types};
+use crate::http::{secret_header, REDACTED};
+use crate::limiter::RateLimiter;
+use crate::retry::Retry;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::multipart::{Form, Part};
+use reqwest::{header, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::Serialize;
-use tracing::info;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
 use url::Url;
 
 const API_VERSION: &str = "v4";
+/// Scopes of tokens which allow reading the API
+pub const READ_API_SCOPES: &[&str] = &["api", "read_api"];
+/// Scopes of tokens which allow writing the API, e.g. creating projects
+pub const API_SCOPES: &[&str] = &["api"];
+/// Scopes of tokens which allow git fetches over HTTP(S)
+pub const READ_REPOSITORY_SCOPES: &[&str] = &["api", "read_repository", "write_repository"];
+/// A token expiring sooner is warned about
+const TOKEN_EXPIRY_WARNING_DAYS: i64 = 7;
+
+/// Makes a description of a backup project, with the sync date if it isn't disabled
+pub fn make_project_description(description: Option<String>, disable_sync_date: bool) -> String {
+    if disable_sync_date {
+        description.unwrap_or_default()
+    } else {
+        format!(
+            "{} 🦞 Synced: {}",
+            description.unwrap_or_default(),
+            Utc::now().to_rfc3339()
+        )
+    }
+}
+
+/// The URL of the `next` relation of the `Link` header
+fn next_link(headers: &header::HeaderMap) -> Option<Url> {
+    headers
+        .get_all(header::LINK)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            if !params.split(';').any(|p| p.trim() == r#"rel="next""#) {
+                return None;
+            }
+            Url::parse(url.trim().trim_start_matches('<').trim_end_matches('>')).ok()
+        })
+}
+
+/// How requests to the GitLab API are authenticated
+#[derive(Clone)]
+pub enum Auth {
+    /// A personal, group or project access token
+    Token(String),
+    /// An OAuth access token, it's refreshed during the run
+    OAuth(Arc<oauth::Session>),
+    /// A CI/CD job token (`CI_JOB_TOKEN`), only some API endpoints accept it
+    JobToken(String),
+}
+
+/// Secrets are redacted, so they don't get to logs and errors
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Auth::Token(_) => "Token",
+            Auth::OAuth(_) => "OAuth",
+            Auth::JobToken(_) => "JobToken",
+        };
+        f.debug_tuple(kind).field(&REDACTED).finish()
+    }
+}
+
+impl Auth {
+    /// The current secret of the auth
+    pub fn secret(&self) -> String {
+        match self {
+            Auth::Token(token) | Auth::JobToken(token) => token.clone(),
+            Auth::OAuth(session) => session.access_token(),
+        }
+    }
+
+    /// The username of git transfers over HTTP(S), tokens are used with usernames of their owners
+    pub fn git_username(&self) -> Option<&'static str> {
+        match self {
+            Auth::Token(_) => None,
+            Auth::OAuth(_) => Some("oauth2"),
+            Auth::JobToken(_) => Some("gitlab-ci-token"),
+        }
+    }
+
+    /// Credentials for git transfers over HTTP(S), the username is used if the auth
+    /// doesn't have its own one
+    pub fn git_http_auth(&self, username: &str) -> String {
+        format!(
+            "{}:{}",
+            self.git_username().unwrap_or(username),
+            self.secret()
+        )
+    }
+}
+
+impl From<&str> for Auth {
+    fn from(token: &str) -> Self {
+        Auth::Token(token.to_string())
+    }
+}
 
 pub struct Client {
     url: Url,
     http: reqwest::Client,
     disable_sync_date: bool,
-    token: String,
+    auth: Auth,
     limit: u32,
+    retry: Retry,
+    limiter: Option<Arc<RateLimiter>>,
+    version: Option<Version>,
+    keyset_pagination: bool,
+    graphql: bool,
+    statistics: bool,
+    list_concurrency: usize,
 }
 
 impl Client {
     pub fn new(
-        token: &str,
+        auth: impl Into<Auth>,
         mut url: Url,
         opp: Option<u32>,
         disable_sync_date: bool,
     ) -> Result<Self> {
         let http = reqwest::Client::new();
-        let limit = if let Some(opp) = opp { opp } else { 1000 };
-        let token = token.to_string();
+        let limit = opp.unwrap_or(1000);
+        let auth = auth.into();
 
         url.set_path(&format!("api/{}", API_VERSION));
 
@@ -33,11 +147,134 @@ impl Client {
             url,
             http,
             disable_sync_date,
-            token,
+            auth,
             limit,
+            retry: Retry::NONE,
+            limiter: None,
+            version: None,
+            keyset_pagination: false,
+            graphql: false,
+            statistics: false,
+            list_concurrency: 1,
         })
     }
 
+    /// Fetches the version of the instance, so endpoints of newer versions aren't requested.
+    /// If the version can't be fetched (e.g. with a job token), the instance is treated as the latest
+    pub async fn detect_version(mut self) -> Self {
+        match self.get_version().await {
+            Ok(v) => match Version::parse(&v.version) {
+                Some(parsed) => {
+                    info!("GitLab {} at {}", v.version, self.url);
+                    if parsed < version::MIN_SUPPORTED {
+                        warn!(
+                            "GitLab {} is older than {}, some requests may fail",
+                            v.version,
+                            version::MIN_SUPPORTED
+                        );
+                    }
+                    self.version = Some(parsed);
+                }
+                None => warn!("unknown GitLab version {}", v.version),
+            },
+            Err(e) => warn!("failed to detect the GitLab version: {}", e),
+        }
+        self
+    }
+
+    /// Lists projects with keyset pagination instead of offset pagination, it's faster
+    /// on big instances. Instances before GitLab 13.0 use offset pagination anyway
+    pub fn with_keyset_pagination(mut self, enabled: bool) -> Self {
+        self.keyset_pagination = enabled;
+        self
+    }
+
+    /// Fetches up to `concurrency` pages of offset paginated lists at once
+    pub fn with_list_concurrency(mut self, concurrency: usize) -> Self {
+        self.list_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Lists projects with the GraphQL API, a page of projects is one query.
+    /// Instances before GitLab 15.0 and lists of owned projects use the REST API
+    pub fn with_graphql(mut self, enabled: bool) -> Self {
+        self.graphql = enabled;
+        self
+    }
+
+    /// Lists projects with their statistics (sizes), they're returned only to project members
+    /// with at least the Reporter role. GraphQL lists always have them
+    pub fn with_statistics(mut self, enabled: bool) -> Self {
+        self.statistics = enabled;
+        self
+    }
+
+    /// The query of project lists (without filters)
+    fn projects_query(&self) -> String {
+        match self.statistics {
+            true => "&statistics=true".to_string(),
+            false => String::new(),
+        }
+    }
+
+    /// The version of the instance if it's detected
+    pub fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// Checks that the instance has features of the version, an unknown version has all of them
+    pub fn supports(&self, version: Version) -> bool {
+        self.version.is_none_or(|v| v >= version)
+    }
+
+    pub async fn get_version(&self) -> reqwest::Result<types::ServerVersion> {
+        self.request(Method::GET, "version", None, None::<()>)
+            .await?
+            .json()
+            .await
+    }
+
+    /// Retries failed requests with the policy
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            Auth::Token(token) => secret_header(req, "PRIVATE-TOKEN", token),
+            Auth::OAuth(session) => req.bearer_auth(session.access_token()),
+            Auth::JobToken(token) => secret_header(req, "JOB-TOKEN", token),
+        }
+    }
+
+    /// Sends requests with the HTTP client, it can be shared with other clients
+    pub fn with_http(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Limits the rate of requests with the limiter, it can be shared with other clients
+    pub fn with_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Sends the request with retries when the rate limit allows it
+    async fn send(&self, req: RequestBuilder) -> reqwest::Result<Response> {
+        self.retry.send(req, self.limiter.as_deref()).await
+    }
+
+    fn api_url<S: Into<String>>(&self, path: S) -> Url {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}/{}", url.path(), path.into()));
+        url
+    }
+
     fn build_request<S: Into<String>, J: Serialize>(
         &self,
         m: Method,
@@ -45,8 +282,7 @@ impl Client {
         query: Option<String>,
         json: Option<J>,
     ) -> RequestBuilder {
-        let mut url = self.url.clone();
-        url.set_path(&format!("{}/{}", url.path(), path.into()));
+        let mut url = self.api_url(path);
 
         if let Some(query) = query {
             url.set_query(Some(&query));
@@ -55,10 +291,8 @@ impl Client {
         info!("{}", url);
 
         let mut req = self
-            .http
-            .request(m, url)
-            .header("Content-Type", "application/json")
-            .header("PRIVATE-TOKEN", &self.token);
+            .authorize(self.http.request(m, url))
+            .header("Content-Type", "application/json");
 
         if let Some(json) = json {
             req = req.json(&json)
@@ -74,8 +308,7 @@ impl Client {
         query: Option<String>,
         json: Option<J>,
     ) -> reqwest::Result<Response> {
-        self.build_request(m, path, query, json)
-            .send()
+        self.send(self.build_request(m, path, query, json))
             .await?
             .error_for_status()
     }
@@ -106,59 +339,796 @@ impl Client {
         self.exist(self.get_project(path).await)
     }
 
-    pub async fn get_projects(
+    /// Fetches projects by their ids or full paths, `list_concurrency` projects at once.
+    /// Projects which aren't found are skipped with a warning
+    pub async fn get_listed_projects(&self, projects: &[String]) -> Result<Vec<types::Project>> {
+        let query = self.projects_query();
+        let query = (!query.is_empty()).then(|| query.trim_start_matches('&').to_string());
+        let projects: Vec<Option<types::Project>> = stream::iter(projects)
+            .map(|project| {
+                let query = query.clone();
+                async move {
+                    let path = format!("projects/{}", urlencoding::encode(project));
+                    let resp = self.request(Method::GET, path, query, None::<()>).await;
+                    match self.exist(resp)? {
+                        Some(resp) => Ok::<_, Error>(Some(resp.json::<types::Project>().await?)),
+                        None => {
+                            warn!("the project {} isn't found, it's skipped", project);
+                            Ok(None)
+                        }
+                    }
+                }
+            })
+            .buffered(self.list_concurrency)
+            .try_collect()
+            .await?;
+
+        Ok(projects
+            .into_iter()
+            .flatten()
+            .filter(|p| !p.empty_repo)
+            .collect())
+    }
+
+    /// Fetches the page of a list with the headers of the response
+    async fn get_page<T: DeserializeOwned>(
         &self,
-        only_owned: bool,
-        only_membership: bool,
-    ) -> Result<Vec<types::Project>> {
-        let mut projects: Vec<types::Project> = vec![];
-        let mut next_page = 1;
+        path: &str,
+        query: &str,
+        page: u64,
+    ) -> Result<(Vec<T>, header::HeaderMap)> {
+        let query = format!("per_page={}&page={}{}", &self.limit, page, query);
+        let resp = self
+            .request(Method::GET, path, Some(query), None::<()>)
+            .await?;
+        let headers = resp.headers().clone();
+        Ok((resp.json::<Vec<T>>().await?, headers))
+    }
+
+    /// Fetches all pages of a list. If the first page shows the number of pages, the rest
+    /// of them are fetched concurrently, otherwise (e.g. lists of more than 10000 objects)
+    /// pages are fetched one by one
+    async fn get_all<T: DeserializeOwned>(&self, path: &str, query: String) -> Result<Vec<T>> {
+        let (mut objects, headers) = self.get_page::<T>(path, &query, 1).await?;
+        let total_pages = headers
+            .get("x-total-pages")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+
+        if let Some(total_pages) = total_pages.filter(|_| self.list_concurrency > 1) {
+            let mut pages = stream::iter(2..=total_pages)
+                .map(|page| self.get_page::<T>(path, &query, page))
+                .buffered(self.list_concurrency);
+            while let Some(page) = pages.next().await {
+                objects.append(&mut page?.0);
+            }
+            return Ok(objects);
+        }
 
+        let mut headers = headers;
+        let mut next_page = 1;
         loop {
-            let mut query = format!("per_page={}&page={}", &self.limit, next_page);
-            if only_owned {
-                query += "&owned=true"
+            let next_page_header = headers.get("x-next-page").unwrap();
+            if next_page_header.is_empty() {
+                break;
             }
-            if only_membership {
-                query += "&only_membership=true"
+
+            next_page += 1;
+            let (mut page, next_headers) = self.get_page::<T>(path, &query, next_page).await?;
+            objects.append(&mut page);
+            headers = next_headers;
+        }
+
+        Ok(objects)
+    }
+
+    /// Fetches all pages of a list with keyset pagination, ordered by ids.
+    /// The next page is requested with the query of the `next` link of the previous one
+    async fn get_all_keyset<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: String,
+    ) -> Result<Vec<T>> {
+        let mut objects: Vec<T> = vec![];
+        let mut query = format!(
+            "pagination=keyset&order_by=id&sort=asc&per_page={}{}",
+            &self.limit, query
+        );
+
+        loop {
+            let resp = self
+                .request(Method::GET, path, Some(query), None::<()>)
+                .await?;
+            let next = next_link(resp.headers());
+
+            objects.append(&mut resp.json::<Vec<T>>().await?);
+
+            match next.and_then(|url| url.query().map(str::to_string)) {
+                Some(next) => query = next,
+                None => break,
             }
+        }
+
+        Ok(objects)
+    }
+
+    /// Fetches all projects (or projects of the user's membership) with GraphQL
+    async fn get_projects_graphql(&self, only_membership: bool) -> Result<Vec<types::Project>> {
+        let mut url = self.url.clone();
+        url.set_path("api/graphql");
+        let mut web_url = self.url.clone();
+        web_url.set_path("");
+
+        let mut projects = vec![];
+        let mut after: Option<String> = None;
+        loop {
+            info!("{} projects after {:?}", url, after);
+            let body = serde_json::json!({
+                "query": graphql::PROJECTS_QUERY,
+                "variables": {
+                    "first": self.limit.min(graphql::MAX_PAGE_SIZE),
+                    "after": after,
+                    "membership": only_membership.then_some(true),
+                },
+            });
+            let req = match &self.auth {
+                Auth::Token(token) => self.http.post(url.clone()).bearer_auth(token),
+                _ => self.authorize(self.http.post(url.clone())),
+            };
             let resp = self
-                .request(Method::GET, "projects", Some(query), None::<()>)
+                .send(req.json(&body))
+                .await?
+                .error_for_status()?
+                .json::<Value>()
                 .await?;
-            let headers = resp.headers().clone();
+            let mut page = graphql::parse_projects(resp, web_url.as_str())?;
+            projects.append(&mut page.projects);
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        Ok(projects)
+    }
 
-            projects.append(&mut resp.json::<Vec<types::Project>>().await?);
+    pub async fn get_projects(
+        &self,
+        only_owned: bool,
+        only_membership: bool,
+    ) -> Result<Vec<types::Project>> {
+        if self.graphql && self.supports(version::GRAPHQL_PROJECTS) {
+            if !only_owned {
+                let mut projects = self.get_projects_graphql(only_membership).await?;
+                projects.retain(|p| !p.empty_repo);
+                return Ok(projects);
+            }
+            warn!("owned projects can't be listed with GraphQL, the REST API is used");
+        }
+        let mut query = self.projects_query();
+        if only_owned {
+            query += "&owned=true"
+        }
+        if only_membership {
+            query += "&only_membership=true"
+        }
+        let mut projects: Vec<types::Project> =
+            if self.keyset_pagination && self.supports(version::KEYSET_PAGINATION) {
+                self.get_all_keyset("projects", query).await?
+            } else {
+                self.get_all("projects", query).await?
+            };
 
-            let next_page_header = headers.get("x-next-page").unwrap();
-            if next_page_header.is_empty() {
-                break;
+        projects.retain(|p| !p.empty_repo);
+
+        Ok(projects)
+    }
+
+    /// Fetches projects of the group and its subgroups, `only_membership` keeps projects
+    /// which the user is a member of (directly or by a group)
+    pub async fn get_group_projects(
+        &self,
+        group: &str,
+        with_shared: bool,
+        only_owned: bool,
+        only_membership: bool,
+    ) -> Result<Vec<types::Project>> {
+        let mut query = self.projects_query() + "&include_subgroups=true";
+        query += &format!("&with_shared={}", with_shared);
+        if only_owned {
+            query += "&owned=true"
+        }
+        if only_membership {
+            query += "&min_access_level=10"
+        }
+        let path = format!("groups/{}/projects", urlencoding::encode(group));
+        let mut projects: Vec<types::Project> = match self.get_all(&path, query).await {
+            Err(Error::Api(e)) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                return Err(Error::Params(format!("the group {} isn't found", group)));
             }
+            result => result?,
+        };
 
-            next_page += 1;
+        projects.retain(|p| !p.empty_repo);
+
+        Ok(projects)
+    }
+
+    /// Fetches personal projects of the user (by the username)
+    pub async fn get_user_projects(
+        &self,
+        username: &str,
+        only_owned: bool,
+        only_membership: bool,
+    ) -> Result<Vec<types::Project>> {
+        let user = self
+            .get_user_by_username(username)
+            .await?
+            .ok_or_else(|| Error::Params(format!("the user {} isn't found", username)))?;
+        self.get_projects_of_user(user.id, only_owned, only_membership)
+            .await
+    }
+
+    /// Fetches personal projects of all users, `list_concurrency` users at once.
+    /// Only admins see all users and their private projects
+    pub async fn get_all_personal_projects(
+        &self,
+        only_owned: bool,
+        only_membership: bool,
+    ) -> Result<Vec<types::Project>> {
+        let users: Vec<types::UserBasic> = self.get_all("users", String::new()).await?;
+        info!("listing personal projects of {} users", users.len());
+        let projects: Vec<Vec<types::Project>> = stream::iter(users)
+            .map(|user| self.get_projects_of_user(user.id, only_owned, only_membership))
+            .buffered(self.list_concurrency)
+            .try_collect()
+            .await?;
+        Ok(projects.into_iter().flatten().collect())
+    }
+
+    async fn get_projects_of_user(
+        &self,
+        id: types::UserId,
+        only_owned: bool,
+        only_membership: bool,
+    ) -> Result<Vec<types::Project>> {
+        let mut query = self.projects_query();
+        if only_owned {
+            query += "&owned=true"
         }
+        if only_membership {
+            query += "&membership=true"
+        }
+        let mut projects: Vec<types::Project> = self
+            .get_all(&format!("users/{}/projects", id), query)
+            .await?;
 
         projects.retain(|p| !p.empty_repo);
 
         Ok(projects)
     }
 
-    fn make_project_description(&self, new_description: Option<String>) -> String {
-        if self.disable_sync_date {
-            new_description.unwrap_or_default()
+    /// Fetches languages of the project repository with their shares of code in percents
+    pub async fn get_project_languages(
+        &self,
+        id: types::ProjectId,
+    ) -> reqwest::Result<BTreeMap<String, f64>> {
+        self.request(
+            Method::GET,
+            &format!("projects/{}/languages", id),
+            None,
+            None::<()>,
+        )
+        .await?
+        .json()
+        .await
+    }
+
+    pub async fn get_project_snippets(
+        &self,
+        id: types::ProjectId,
+    ) -> Result<Vec<types::ProjectSnippet>> {
+        self.get_all(&format!("projects/{}/snippets", id), String::new())
+            .await
+    }
+
+    /// Fetches personal snippets of the current user
+    pub async fn get_snippets(&self) -> Result<Vec<types::ProjectSnippet>> {
+        self.get_all("snippets", String::new()).await
+    }
+
+    /// Fetches all issues of the project as raw JSON
+    pub async fn get_issues(&self, id: types::ProjectId) -> Result<Vec<Value>> {
+        self.get_all(&format!("projects/{}/issues", id), "&sort=asc".into())
+            .await
+    }
+
+    /// Fetches all notes (comments and system notes) of the issue as raw JSON
+    pub async fn get_issue_notes(&self, id: types::ProjectId, iid: u64) -> Result<Vec<Value>> {
+        self.get_all(
+            &format!("projects/{}/issues/{}/notes", id, iid),
+            "&sort=asc".into(),
+        )
+        .await
+    }
+
+    /// Fetches all merge requests of the project as raw JSON
+    pub async fn get_merge_requests(&self, id: types::ProjectId) -> Result<Vec<Value>> {
+        self.get_all(
+            &format!("projects/{}/merge_requests", id),
+            "&state=all&sort=asc".into(),
+        )
+        .await
+    }
+
+    /// Fetches the merge request with diff refs as raw JSON
+    pub async fn get_merge_request(
+        &self,
+        id: types::ProjectId,
+        iid: u64,
+    ) -> reqwest::Result<Value> {
+        self.request(
+            Method::GET,
+            format!("projects/{}/merge_requests/{}", id, iid),
+            None,
+            None::<()>,
+        )
+        .await?
+        .json::<Value>()
+        .await
+    }
+
+    /// Fetches approvals of the merge request, `None` if approvals aren't available
+    pub async fn get_merge_request_approvals(
+        &self,
+        id: types::ProjectId,
+        iid: u64,
+    ) -> reqwest::Result<Option<Value>> {
+        let resp = self
+            .request(
+                Method::GET,
+                format!("projects/{}/merge_requests/{}/approvals", id, iid),
+                None,
+                None::<()>,
+            )
+            .await;
+        match self.exist(resp)? {
+            Some(resp) => Ok(Some(resp.json::<Value>().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetches all discussions (review threads) of the merge request as raw JSON
+    pub async fn get_merge_request_discussions(
+        &self,
+        id: types::ProjectId,
+        iid: u64,
+    ) -> Result<Vec<Value>> {
+        self.get_all(
+            &format!("projects/{}/merge_requests/{}/discussions", id, iid),
+            String::new(),
+        )
+        .await
+    }
+
+    pub async fn get_variables(&self, id: types::ProjectId) -> Result<Vec<types::ProjectVariable>> {
+        self.get_all(&format!("projects/{}/variables", id), String::new())
+            .await
+    }
+
+    /// Updates the CI/CD variable of the project or creates it, if it doesn't exist
+    pub async fn set_variable(
+        &self,
+        id: types::ProjectId,
+        variable: &types::ProjectVariable,
+    ) -> reqwest::Result<types::ProjectVariable> {
+        let query = format!(
+            "filter[environment_scope]={}",
+            urlencoding::encode(&variable.environment_scope)
+        );
+        let resp = self
+            .request(
+                Method::PUT,
+                format!(
+                    "projects/{}/variables/{}",
+                    id,
+                    urlencoding::encode(&variable.key)
+                ),
+                Some(query),
+                Some(variable),
+            )
+            .await;
+
+        match self.exist(resp)? {
+            Some(resp) => resp.json::<types::ProjectVariable>().await,
+            None => {
+                self.request(
+                    Method::POST,
+                    format!("projects/{}/variables", id),
+                    None,
+                    Some(variable),
+                )
+                .await?
+                .json::<types::ProjectVariable>()
+                .await
+            }
+        }
+    }
+
+    /// Fetches direct members of the project
+    pub async fn get_members(&self, id: types::ProjectId) -> Result<Vec<types::Member>> {
+        self.get_all(&format!("projects/{}/members", id), String::new())
+            .await
+    }
+
+    /// Fetches all members of the group including inherited members of its ancestor groups
+    pub async fn get_group_members(&self, id: types::GroupId) -> Result<Vec<types::Member>> {
+        self.get_all(&format!("groups/{}/members/all", id), String::new())
+            .await
+    }
+
+    /// Finds the user by the username
+    pub async fn get_user_by_username(
+        &self,
+        username: &str,
+    ) -> reqwest::Result<Option<types::UserBasic>> {
+        let query = format!("username={}", urlencoding::encode(username));
+        let users = self
+            .request(Method::GET, "users", Some(query), None::<()>)
+            .await?
+            .json::<Vec<types::UserBasic>>()
+            .await?;
+        Ok(users.into_iter().next())
+    }
+
+    /// Adds the user to members of the project or updates the access level and the expiry date,
+    /// if the user is a member already
+    pub async fn set_member(
+        &self,
+        id: types::ProjectId,
+        user_id: types::UserId,
+        member: &types::Member,
+    ) -> reqwest::Result<()> {
+        #[derive(Serialize)]
+        struct MemberRequest<'a> {
+            user_id: types::UserId,
+            access_level: u64,
+            expires_at: &'a Option<NaiveDate>,
+        }
+
+        let data = &MemberRequest {
+            user_id,
+            access_level: member.access_level,
+            expires_at: &member.expires_at,
+        };
+        let resp = self
+            .request(
+                Method::POST,
+                format!("projects/{}/members", id),
+                None,
+                Some(data),
+            )
+            .await;
+
+        match resp {
+            Err(e) if e.status() == Some(StatusCode::CONFLICT) => {
+                self.request(
+                    Method::PUT,
+                    format!("projects/{}/members/{}", id, user_id),
+                    None,
+                    Some(data),
+                )
+                .await?;
+                Ok(())
+            }
+            resp => resp.map(|_| ()),
+        }
+    }
+
+    /// Fetches protected branch rules of the project
+    pub async fn get_protected_branches(
+        &self,
+        id: types::ProjectId,
+    ) -> Result<Vec<types::ProtectedRepoBranch>> {
+        self.get_all(
+            &format!("projects/{}/protected_branches", id),
+            String::new(),
+        )
+        .await
+    }
+
+    /// Protects branches of the project by the rule, an existing rule with the same name
+    /// is replaced. Only role-based access levels are applied
+    pub async fn set_protected_branch(
+        &self,
+        id: types::ProjectId,
+        branch: &types::ProtectedRepoBranch,
+    ) -> reqwest::Result<()> {
+        #[derive(Serialize)]
+        struct ProtectRequest<'a> {
+            name: &'a str,
+            push_access_level: u64,
+            merge_access_level: u64,
+            allow_force_push: bool,
+            code_owner_approval_required: Option<bool>,
+        }
+
+        let path = format!("projects/{}/protected_branches", id);
+        let resp = self
+            .request(
+                Method::DELETE,
+                format!("{}/{}", &path, urlencoding::encode(&branch.name)),
+                None,
+                None::<()>,
+            )
+            .await;
+        self.exist(resp)?;
+
+        let data = &ProtectRequest {
+            name: &branch.name,
+            push_access_level: types::PRBAccessLevel::role_level(&branch.push_access_levels),
+            merge_access_level: types::PRBAccessLevel::role_level(&branch.merge_access_levels),
+            allow_force_push: branch.allow_force_push,
+            code_owner_approval_required: branch.code_owner_approval_required,
+        };
+        self.request(Method::POST, path, None, Some(data)).await?;
+        Ok(())
+    }
+
+    /// Fetches badges of the project including badges of its groups
+    pub async fn get_badges(&self, id: types::ProjectId) -> Result<Vec<types::Badge>> {
+        self.get_all(&format!("projects/{}/badges", id), String::new())
+            .await
+    }
+
+    /// Adds the badge to the project
+    pub async fn add_badge(
+        &self,
+        id: types::ProjectId,
+        badge: &types::Badge,
+    ) -> reqwest::Result<()> {
+        #[derive(Serialize)]
+        struct BadgeRequest<'a> {
+            name: &'a Option<String>,
+            link_url: &'a str,
+            image_url: &'a str,
+        }
+
+        let data = &BadgeRequest {
+            name: &badge.name,
+            link_url: &badge.link_url,
+            image_url: &badge.image_url,
+        };
+        self.request(
+            Method::POST,
+            format!("projects/{}/badges", id),
+            None,
+            Some(data),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Fetches deploy keys of the project
+    pub async fn get_deploy_keys(&self, id: types::ProjectId) -> Result<Vec<types::DeployKey>> {
+        self.get_all(&format!("projects/{}/deploy_keys", id), String::new())
+            .await
+    }
+
+    /// Adds the deploy key to the project
+    pub async fn add_deploy_key(
+        &self,
+        id: types::ProjectId,
+        key: &types::DeployKey,
+    ) -> reqwest::Result<()> {
+        #[derive(Serialize)]
+        struct DeployKeyRequest<'a> {
+            title: &'a str,
+            key: &'a str,
+            can_push: bool,
+            expires_at: Option<DateTime<Utc>>,
+        }
+
+        let data = &DeployKeyRequest {
+            title: &key.title,
+            key: &key.key,
+            can_push: key.can_push,
+            expires_at: key.expires_at,
+        };
+        self.request(
+            Method::POST,
+            format!("projects/{}/deploy_keys", id),
+            None,
+            Some(data),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Fetches deploy tokens (names, usernames and scopes) of the project as raw JSON
+    pub async fn get_deploy_tokens(&self, id: types::ProjectId) -> Result<Vec<Value>> {
+        self.get_all(&format!("projects/{}/deploy_tokens", id), String::new())
+            .await
+    }
+
+    /// Fetches webhooks of the project as raw JSON
+    pub async fn get_hooks(&self, id: types::ProjectId) -> Result<Vec<Value>> {
+        self.get_all(&format!("projects/{}/hooks", id), String::new())
+            .await
+    }
+
+    /// Integrations were services before GitLab 14.4
+    fn integrations_path(&self) -> &'static str {
+        if self.supports(version::INTEGRATIONS) {
+            "integrations"
         } else {
-            format!(
-                "{} 🦞 Synced: {}",
-                new_description.unwrap_or_default(),
-                Utc::now().to_rfc3339()
+            "services"
+        }
+    }
+
+    /// Fetches integrations (services) of the project as raw JSON, the list isn't paginated
+    pub async fn get_integrations(&self, id: types::ProjectId) -> reqwest::Result<Vec<Value>> {
+        self.request(
+            Method::GET,
+            format!("projects/{}/{}", id, self.integrations_path()),
+            None,
+            None::<()>,
+        )
+        .await?
+        .json::<Vec<Value>>()
+        .await
+    }
+
+    /// Fetches the integration of the project with its properties as raw JSON
+    pub async fn get_integration(
+        &self,
+        id: types::ProjectId,
+        slug: &str,
+    ) -> reqwest::Result<Value> {
+        self.request(
+            Method::GET,
+            format!("projects/{}/{}/{}", id, self.integrations_path(), slug),
+            None,
+            None::<()>,
+        )
+        .await?
+        .json::<Value>()
+        .await
+    }
+
+    /// Fetches all labels of the project including labels of its ancestor groups
+    pub async fn get_labels(&self, id: types::ProjectId) -> Result<Vec<types::Label>> {
+        self.get_all(
+            &format!("projects/{}/labels", id),
+            "&include_ancestor_groups=true".into(),
+        )
+        .await
+    }
+
+    /// Updates the label of the project or creates it, if it doesn't exist
+    pub async fn set_label(
+        &self,
+        id: types::ProjectId,
+        label: &types::Label,
+    ) -> reqwest::Result<()> {
+        #[derive(Serialize)]
+        struct LabelRequest<'a> {
+            name: &'a str,
+            color: &'a types::LabelColor,
+            description: &'a Option<String>,
+            priority: Option<u64>,
+        }
+
+        let data = &LabelRequest {
+            name: &label.name,
+            color: &label.color,
+            description: &label.description,
+            priority: label.priority,
+        };
+        let resp = self
+            .request(
+                Method::PUT,
+                format!(
+                    "projects/{}/labels/{}",
+                    id,
+                    urlencoding::encode(&label.name)
+                ),
+                None,
+                Some(data),
+            )
+            .await;
+
+        if self.exist(resp)?.is_none() {
+            self.request(
+                Method::POST,
+                format!("projects/{}/labels", id),
+                None,
+                Some(data),
             )
+            .await?;
         }
+        Ok(())
+    }
+
+    /// Fetches all milestones of the project
+    pub async fn get_milestones(&self, id: types::ProjectId) -> Result<Vec<types::Milestone>> {
+        self.get_all(&format!("projects/{}/milestones", id), String::new())
+            .await
+    }
+
+    /// Fetches all milestones of the group including milestones of its ancestor groups
+    pub async fn get_group_milestones(&self, id: types::GroupId) -> Result<Vec<types::Milestone>> {
+        self.get_all(
+            &format!("groups/{}/milestones", id),
+            "&include_ancestors=true".into(),
+        )
+        .await
+    }
+
+    /// Updates the milestone of the project with the same title or creates it,
+    /// if it doesn't exist. The state (active or closed) is kept
+    pub async fn set_milestone(
+        &self,
+        id: types::ProjectId,
+        milestone: &types::Milestone,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        struct MilestoneRequest<'a> {
+            title: &'a str,
+            description: &'a Option<String>,
+            due_date: Option<NaiveDate>,
+            start_date: Option<NaiveDate>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            state_event: Option<&'a str>,
+        }
+
+        let mut data = MilestoneRequest {
+            title: &milestone.title,
+            description: &milestone.description,
+            due_date: milestone.due_date,
+            start_date: milestone.start_date,
+            state_event: None,
+        };
+        let query = format!("&title={}", urlencoding::encode(&milestone.title));
+        let existing: Vec<types::Milestone> = self
+            .get_all(&format!("projects/{}/milestones", id), query)
+            .await?;
+
+        let milestone_id = match existing.first() {
+            Some(m) => m.id,
+            None => {
+                self.request(
+                    Method::POST,
+                    format!("projects/{}/milestones", id),
+                    None,
+                    Some(&data),
+                )
+                .await?
+                .json::<types::Milestone>()
+                .await?
+                .id
+            }
+        };
+
+        data.state_event = Some(match milestone.state {
+            types::MilestoneState::Active => "activate",
+            types::MilestoneState::Closed => "close",
+        });
+        self.request(
+            Method::PUT,
+            format!("projects/{}/milestones/{}", id, milestone_id),
+            None,
+            Some(&data),
+        )
+        .await?;
+        Ok(())
     }
 
     pub async fn make_project(
         &self,
+        path: String,
         name: String,
         group_id: types::GroupId,
-        info: &types::Project,
+        description: Option<String>,
     ) -> reqwest::Result<types::Project> {
         #[derive(Serialize)]
         struct MakeProjectRequest {
@@ -168,9 +1138,8 @@ impl Client {
             namespace_id: types::GroupId,
         }
 
-        let path = name.clone();
         let namespace_id = group_id;
-        let description = self.make_project_description(info.description.clone());
+        let description = make_project_description(description, self.disable_sync_date);
 
         let data = &MakeProjectRequest {
             name,
@@ -187,14 +1156,14 @@ impl Client {
     pub async fn update_project(
         &self,
         project: &types::Project,
-        info: &types::Project,
+        description: Option<String>,
     ) -> reqwest::Result<types::Project> {
         #[derive(Serialize)]
         struct UpdateProjectRequest {
             description: String,
         }
 
-        let description = self.make_project_description(info.description.clone());
+        let description = make_project_description(description, self.disable_sync_date);
         let data = &UpdateProjectRequest { description };
 
         self.request(
@@ -208,6 +1177,49 @@ impl Client {
         .await
     }
 
+    pub async fn archive_project(&self, id: types::ProjectId) -> reqwest::Result<()> {
+        self.request(
+            Method::POST,
+            format!("projects/{}/archive", id),
+            None,
+            None::<()>,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_project(&self, id: types::ProjectId) -> reqwest::Result<()> {
+        self.request(Method::DELETE, format!("projects/{}", id), None, None::<()>)
+            .await?;
+        Ok(())
+    }
+
+    /// Applies settings of the source project (topics, default branch and visibility)
+    pub async fn update_project_settings(
+        &self,
+        id: types::ProjectId,
+        info: &types::Project,
+    ) -> reqwest::Result<types::Project> {
+        #[derive(Serialize)]
+        struct UpdateSettingsRequest<'a> {
+            topics: &'a [String],
+            #[serde(skip_serializing_if = "Option::is_none")]
+            default_branch: &'a Option<String>,
+            visibility: types::VisibilityLevel,
+        }
+
+        let data = &UpdateSettingsRequest {
+            topics: &info.topics,
+            default_branch: &info.default_branch,
+            visibility: info.visibility,
+        };
+
+        self.request(Method::PUT, format!("projects/{}", id), None, Some(data))
+            .await?
+            .json::<types::Project>()
+            .await
+    }
+
     pub async fn get_group(&self, path: String) -> reqwest::Result<types::Group> {
         let path = urlencoding::encode(&path);
         self.request(Method::GET, format!("groups/{}", path), None, None::<()>)
@@ -222,6 +1234,7 @@ impl Client {
 
     pub async fn make_subgroup(
         &self,
+        path: String,
         name: String,
         parent_id: Option<types::GroupId>,
     ) -> reqwest::Result<types::Group> {
@@ -232,7 +1245,6 @@ impl Client {
             parent_id: Option<types::GroupId>,
         }
 
-        let path = name.clone();
         let data = &MakeGroupRequest {
             name,
             path,
@@ -245,59 +1257,464 @@ impl Client {
             .await
     }
 
-    pub async fn make_project_with_namespace(
+    /// Creates missing groups of the path inside the root group, `names` are display
+    /// names of the created groups (their paths are used if missing),
+    /// returns the full path and the id of the last group
+    pub async fn make_namespace(
         &self,
-        mut path: Vec<String>,
+        path: Vec<String>,
+        names: &[String],
         root_group: &Option<types::Group>,
-        project_info: &types::Project,
-    ) -> reqwest::Result<types::Project> {
+    ) -> reqwest::Result<(String, Option<types::GroupId>)> {
         let mut parent_id = root_group.as_ref().map(|gr| gr.id);
-        let project_name = path.pop().expect("invalid project path");
         let mut current_namespace = root_group
             .as_ref()
             .map(|gr| gr.full_path.clone())
             .unwrap_or_default();
 
-        for group_name in path {
+        for (i, group_path) in path.into_iter().enumerate() {
             current_namespace = if current_namespace.is_empty() {
-                group_name.clone()
+                group_path.clone()
             } else {
-                format!("{}/{}", current_namespace, group_name)
+                format!("{}/{}", current_namespace, group_path)
             };
             let group = if let Some(group) = self.group_exist(current_namespace.clone()).await? {
                 group
             } else {
-                self.make_subgroup(group_name, parent_id).await?
+                let name = names.get(i).cloned().unwrap_or_else(|| group_path.clone());
+                self.make_subgroup(group_path, name, parent_id).await?
             };
 
             parent_id = Some(group.id);
         }
 
+        Ok((current_namespace, parent_id))
+    }
+
+    pub async fn make_project_with_namespace(
+        &self,
+        mut path: Vec<String>,
+        root_group: &Option<types::Group>,
+        project_info: &types::Project,
+    ) -> reqwest::Result<types::Project> {
+        let project_path = path.pop().expect("invalid project path");
+        // display names of the source namespaces, the last ones match the groups of the path
+        let mut names: Vec<String> = project_info
+            .name_with_namespace
+            .split(" / ")
+            .map(str::to_string)
+            .collect();
+        names.pop();
+        let names = &names[names.len().saturating_sub(path.len())..];
+        let (current_namespace, parent_id) = self.make_namespace(path, names, root_group).await?;
+        let description = project_info.description.clone();
+
         match self
-            .project_exist(format!("{}/{}", current_namespace, project_name))
+            .project_exist(format!("{}/{}", current_namespace, project_path))
             .await?
         {
-            Some(p) => self.update_project(&p, project_info).await,
+            Some(p) => self.update_project(&p, description).await,
             None => {
                 self.make_project(
-                    project_name,
+                    project_path,
+                    project_info.name.clone(),
                     parent_id.unwrap_or_else(|| {
                         panic!(
                             "Parent group for project {} not found",
                             &project_info.name_with_namespace
                         )
                     }),
-                    project_info,
+                    description,
                 )
                 .await
             }
         }
     }
 
+    pub async fn schedule_export(&self, id: types::ProjectId) -> reqwest::Result<()> {
+        self.request(
+            Method::POST,
+            format!("projects/{}/export", id),
+            None,
+            None::<()>,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_export(&self, id: types::ProjectId) -> reqwest::Result<types::ProjectExport> {
+        self.request(
+            Method::GET,
+            format!("projects/{}/export", id),
+            None,
+            None::<()>,
+        )
+        .await?
+        .json::<types::ProjectExport>()
+        .await
+    }
+
+    /// Downloads a finished export archive of the project to the file
+    pub async fn download_export(&self, id: types::ProjectId, file: &str) -> Result<()> {
+        let mut resp = self
+            .request(
+                Method::GET,
+                format!("projects/{}/export/download", id),
+                None,
+                None::<()>,
+            )
+            .await?;
+
+        let mut out = tokio::fs::File::create(file).await?;
+        while let Some(chunk) = resp.chunk().await? {
+            out.write_all(&chunk).await?;
+        }
+        out.flush().await?;
+
+        Ok(())
+    }
+
+    /// Fetches all container registry repositories of the project with their tags
+    pub async fn get_registry_repositories(
+        &self,
+        id: types::ProjectId,
+    ) -> Result<Vec<types::RegistryRepository>> {
+        self.get_all(
+            &format!("projects/{}/registry/repositories", id),
+            "&tags=true".into(),
+        )
+        .await
+    }
+
+    /// Fetches all packages of the package registry of the project
+    pub async fn get_packages(&self, id: types::ProjectId) -> Result<Vec<types::Package>> {
+        self.get_all(&format!("projects/{}/packages", id), String::new())
+            .await
+    }
+
+    /// Fetches all files of the package
+    pub async fn get_package_files(
+        &self,
+        id: types::ProjectId,
+        package_id: u64,
+    ) -> Result<Vec<types::PackageFile>> {
+        self.get_all(
+            &format!("projects/{}/packages/{}/package_files", id, package_id),
+            String::new(),
+        )
+        .await
+    }
+
+    /// A URL of the package file in the registry API of the package type,
+    /// `None` if the type isn't supported (generic, maven, npm and pypi are supported)
+    pub fn package_file_url(
+        &self,
+        id: types::ProjectId,
+        package: &types::Package,
+        file: &types::PackageFile,
+    ) -> Option<Url> {
+        let name = &file.file_name;
+        let path = match package.package_type.as_str() {
+            "generic" => format!(
+                "projects/{}/packages/generic/{}/{}/{}",
+                id, &package.name, &package.version, name
+            ),
+            "maven" => format!(
+                "projects/{}/packages/maven/{}/{}/{}",
+                id, &package.name, &package.version, name
+            ),
+            "npm" => format!("projects/{}/packages/npm/{}/-/{}", id, &package.name, name),
+            "pypi" => format!(
+                "projects/{}/packages/pypi/files/{}/{}",
+                id,
+                file.file_sha256.as_ref()?,
+                name
+            ),
+            _ => return None,
+        };
+        Some(self.api_url(path))
+    }
+
+    /// Uploads the file to the package registry by the URL of [`Client::package_file_url`],
+    /// only generic and maven packages take raw uploads
+    pub async fn upload_package_file(&self, url: Url, file: &str) -> Result<()> {
+        info!("{}", url);
+        let req = self
+            .authorize(self.http.put(url))
+            .body(tokio::fs::File::open(file).await?);
+        self.send(req).await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Fetches all releases of the project with their assets as raw JSON
+    pub async fn get_releases(&self, id: types::ProjectId) -> Result<Vec<Value>> {
+        self.get_all(&format!("projects/{}/releases", id), String::new())
+            .await
+    }
+
+    /// Makes a GET request by the URL, the token is sent only to the GitLab instance
+    fn get_url(&self, url: Url) -> RequestBuilder {
+        info!("{}", url);
+        if url.origin() == self.url.origin() {
+            self.authorize(self.http.get(url))
+        } else {
+            self.http.get(url)
+        }
+    }
+
+    /// Downloads a small file (e.g. an avatar) by the URL to memory
+    pub async fn get_file(&self, url: &str) -> Result<Vec<u8>> {
+        let url = Url::parse(url)?;
+        let resp = self.send(self.get_url(url)).await?.error_for_status()?;
+        Ok(resp.bytes().await?.to_vec())
+    }
+
+    /// Uploads the image as the avatar of the project
+    pub async fn set_avatar(
+        &self,
+        id: types::ProjectId,
+        file_name: String,
+        image: Vec<u8>,
+    ) -> reqwest::Result<()> {
+        let form = Form::new().part("avatar", Part::bytes(image).file_name(file_name));
+        let url = self.api_url(format!("projects/{}", id));
+        info!("{}", url);
+
+        let req = self.authorize(self.http.put(url)).multipart(form);
+        self.send(req).await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Downloads the file by the URL, a partially downloaded file (`<file>.part`) is resumed.
+    /// The token is sent only to the GitLab instance
+    pub async fn download_file(&self, url: &str, file: &str) -> Result<()> {
+        let url = Url::parse(url)?;
+        let part = format!("{}.part", file);
+        let offset = tokio::fs::metadata(&part)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        let mut req = self.get_url(url);
+        if offset > 0 {
+            req = req.header(header::RANGE, format!("bytes={}-", offset));
+        }
+        let resp = self.send(req).await?;
+
+        if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            tokio::fs::rename(&part, file).await?;
+            return Ok(());
+        }
+        let mut resp = resp.error_for_status()?;
+        let mut out = if resp.status() == StatusCode::PARTIAL_CONTENT {
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&part)
+                .await?
+        } else {
+            tokio::fs::File::create(&part).await?
+        };
+        while let Some(chunk) = resp.chunk().await? {
+            out.write_all(&chunk).await?;
+        }
+        out.flush().await?;
+        tokio::fs::rename(&part, file).await?;
+
+        Ok(())
+    }
+
+    /// Schedules an import of the export archive to the namespace (a full path of a group)
+    pub async fn import_project(
+        &self,
+        path: String,
+        namespace: String,
+        file: &str,
+    ) -> Result<types::ProjectImport> {
+        let name = Path::new(file)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let archive = Part::stream(tokio::fs::File::open(file).await?).file_name(name);
+        let form = Form::new()
+            .text("path", path)
+            .text("namespace", namespace)
+            .part("file", archive);
+
+        let url = self.api_url("projects/import");
+        info!("{}", url);
+
+        let req = self.authorize(self.http.post(url)).multipart(form);
+        Ok(self
+            .send(req)
+            .await?
+            .error_for_status()?
+            .json::<types::ProjectImport>()
+            .await?)
+    }
+
+    pub async fn get_import(&self, id: types::ProjectId) -> reqwest::Result<types::ProjectImport> {
+        self.request(
+            Method::GET,
+            format!("projects/{}/import", id),
+            None,
+            None::<()>,
+        )
+        .await?
+        .json::<types::ProjectImport>()
+        .await
+    }
+
+    /// The username for git transfers over HTTP(S), the owner of a token is fetched
+    pub async fn git_username(&self) -> Result<String> {
+        match self.auth.git_username() {
+            Some(username) => Ok(username.to_string()),
+            None => Ok(self.get_current_user().await?.username),
+        }
+    }
+
     pub async fn get_current_user(&self) -> reqwest::Result<types::User> {
         self.request(Method::GET, "user", None, None::<()>)
             .await?
             .json::<types::User>()
             .await
     }
+
+    /// The token of requests, `None` if the instance (before GitLab 15.5) doesn't show it
+    pub async fn get_current_token(&self) -> reqwest::Result<Option<types::PersonalAccessToken>> {
+        if !self.supports(version::CURRENT_TOKEN) {
+            return Ok(None);
+        }
+        let resp = self
+            .request(Method::GET, "personal_access_tokens/self", None, None::<()>)
+            .await;
+        match self.exist(resp)? {
+            Some(resp) => resp.json().await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks that the token works before the run, and that it has one of the scopes of every
+    /// listed use (e.g. `("git fetches over HTTP", &["api", "read_repository"])`).
+    /// Scopes and the expiry of the token are logged, `name` is the instance of the token in messages
+    pub async fn check_token(&self, name: &str, uses: &[(&str, &[&str])]) -> Result<()> {
+        if let Auth::JobToken(_) = self.auth {
+            // job tokens can't be checked, and they can't access /user
+            return Ok(());
+        }
+        let user = match self.get_current_user().await {
+            Ok(user) => user,
+            Err(e) if e.status() == Some(StatusCode::UNAUTHORIZED) => {
+                return Err(Error::Auth(format!(
+                    "the {} token is invalid, expired or revoked",
+                    name
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        info!("the {} token belongs to {}", name, user.username);
+        if let Auth::OAuth(_) = self.auth {
+            return Ok(());
+        }
+
+        let token = match self.get_current_token().await {
+            Ok(Some(token)) => token,
+            Ok(None) => return Ok(()),
+            Err(e) if e.status() == Some(StatusCode::FORBIDDEN) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if !token.active || token.revoked {
+            return Err(Error::Auth(format!(
+                "the {} token {} isn't active",
+                name, token.name
+            )));
+        }
+        let expires = match token.expires_at {
+            Some(date) => format!("expires on {}", date),
+            None => "doesn't expire".to_string(),
+        };
+        info!(
+            "the {} token {} has scopes {}, it {}",
+            name,
+            token.name,
+            token.scopes.join(", "),
+            expires
+        );
+        if let Some(date) = token.expires_at {
+            if date - Utc::now().date_naive() < chrono::Duration::days(TOKEN_EXPIRY_WARNING_DAYS) {
+                warn!("the {} token {} {}", name, token.name, expires);
+            }
+        }
+
+        for (what, scopes) in uses {
+            if !scopes.iter().any(|s| token.scopes.iter().any(|t| t == s)) {
+                let needed = match scopes {
+                    [scope] => format!("the scope {}", scope),
+                    _ => format!("one of the scopes {}", scopes.join(", ")),
+                };
+                return Err(Error::Auth(format!(
+                    "the {} token {} needs {} for {}, it has {}",
+                    name,
+                    token.name,
+                    needed,
+                    what,
+                    token.scopes.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const API: &str = "https://gitlab.example.com/api/v4/groups/7/projects";
+
+    fn links(values: &[String]) -> header::HeaderMap {
+        let mut headers = header::HeaderMap::new();
+        for value in values {
+            headers.append(header::LINK, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn next_link_of_offset_pagination() {
+        let headers = links(&[format!(
+            r#"<{api}?page=1&per_page=100>; rel="prev", <{api}?page=3&per_page=100>; rel="next", <{api}?page=1&per_page=100>; rel="first", <{api}?page=9&per_page=100>; rel="last""#,
+            api = API
+        )]);
+        assert_eq!(
+            next_link(&headers).unwrap().as_str(),
+            format!("{}?page=3&per_page=100", API)
+        );
+    }
+
+    #[test]
+    fn next_link_of_several_headers() {
+        let headers = links(&[
+            format!(r#"<{}?page=1>; rel="first""#, API),
+            format!(
+                r#"<{}?id_after=42&pagination=keyset&per_page=100>; rel="next""#,
+                API
+            ),
+        ]);
+        assert_eq!(
+            next_link(&headers).unwrap().as_str(),
+            format!("{}?id_after=42&pagination=keyset&per_page=100", API)
+        );
+    }
+
+    #[test]
+    fn next_link_missing() {
+        let headers = links(&[format!(
+            r#"<{api}?page=1>; rel="prev", <{api}?page=1>; rel="first", <{api}?page=2>; rel="last""#,
+            api = API
+        )]);
+        assert_eq!(next_link(&headers), None);
+        assert_eq!(next_link(&header::HeaderMap::new()), None);
+        let headers = links(&[r#"<not a url>; rel="next""#.to_string()]);
+        assert_eq!(next_link(&headers), None);
+    }
 }
@@ -0,0 +1,202 @@
+//! Listing projects with the GraphQL API: a page of projects with their repositories,
+//! statistics and namespaces is one query, converted to projects of the REST API
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::{Error, Result};
+use crate::gitlab::types;
+
+/// GraphQL returns at most 100 nodes of a connection
+pub(crate) const MAX_PAGE_SIZE: u32 = 100;
+
+pub(crate) const PROJECTS_QUERY: &str = r#"
+query($first: Int, $after: String, $membership: Boolean) {
+  projects(first: $first, after: $after, membership: $membership) {
+    pageInfo { hasNextPage endCursor }
+    nodes {
+      id name path fullPath nameWithNamespace description webUrl httpUrlToRepo sshUrlToRepo
+      archived visibility createdAt lastActivityAt topics starCount forksCount avatarUrl
+      issuesEnabled mergeRequestsEnabled jobsEnabled wikiEnabled snippetsEnabled
+      containerRegistryEnabled lfsEnabled sharedRunnersEnabled openIssuesCount
+      requestAccessEnabled publicJobs removeSourceBranchAfterMerge
+      onlyAllowMergeIfPipelineSucceeds onlyAllowMergeIfAllDiscussionsAreResolved
+      printingMergeRequestLinkEnabled
+      repository { empty rootRef }
+      statistics { commitCount storageSize repositorySize lfsObjectsSize buildArtifactsSize }
+      namespace { id name path fullPath }
+    }
+  }
+}
+"#;
+
+#[derive(Deserialize)]
+struct Response {
+    data: Option<Data>,
+    errors: Option<Vec<QueryError>>,
+}
+
+#[derive(Deserialize)]
+struct QueryError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    projects: Connection,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection {
+    page_info: PageInfo,
+    nodes: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+/// A page of projects and the cursor of the next one
+pub(crate) struct ProjectsPage {
+    pub projects: Vec<types::Project>,
+    pub next: Option<String>,
+}
+
+/// The numeric id of a global id, e.g. `gid://gitlab/Project/42`
+fn number(gid: &Value) -> Result<u64> {
+    gid.as_str()
+        .and_then(|gid| gid.rsplit('/').next())
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| Error::Graphql(format!("invalid id {}", gid)))
+}
+
+/// Converts the project node to the project of the REST API, fields which GraphQL
+/// doesn't have are empty, access levels of features are derived from their flags
+fn rest_project(node: &Value, web_url: &str) -> Result<types::Project> {
+    let enabled = |field: &str| node[field].as_bool().unwrap_or(false);
+    let access_level = |field: &str| {
+        if enabled(field) {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    };
+    let size = |field: &str| node["statistics"][field].as_f64().unwrap_or(0.0) as u64;
+    let namespace = &node["namespace"];
+    let namespace_kind = match namespace["id"].as_str() {
+        Some(gid) if gid.contains("Group") => "group",
+        _ => "user",
+    };
+    let full_path = namespace["fullPath"].as_str().unwrap_or_default();
+    let statistics = if node["statistics"].is_object() {
+        json!({
+            "commit_count": size("commitCount"),
+            "storage_size": size("storageSize"),
+            "repository_size": size("repositorySize"),
+            "lfs_objects_size": size("lfsObjectsSize"),
+            "job_artifacts_size": size("buildArtifactsSize"),
+        })
+    } else {
+        Value::Null
+    };
+    let topics = match &node["topics"] {
+        Value::Array(topics) => Value::Array(topics.clone()),
+        _ => json!([]),
+    };
+
+    let mut project = json!({
+        "id": number(&node["id"])?,
+        "description": node["description"],
+        "default_branch": node["repository"]["rootRef"],
+        "tag_list": topics,
+        "topics": topics,
+        "archived": enabled("archived"),
+        "empty_repo": node["repository"]["empty"].as_bool().unwrap_or(false),
+        "visibility": node["visibility"],
+        "ssh_url_to_repo": node["sshUrlToRepo"],
+        "http_url_to_repo": node["httpUrlToRepo"],
+        "web_url": node["webUrl"],
+        "name": node["name"],
+        "name_with_namespace": node["nameWithNamespace"],
+        "path": node["path"],
+        "path_with_namespace": node["fullPath"],
+        "container_registry_enabled": node["containerRegistryEnabled"],
+        "created_at": node["createdAt"],
+        "last_activity_at": node["lastActivityAt"],
+        "shared_runners_enabled": enabled("sharedRunnersEnabled"),
+        "lfs_enabled": enabled("lfsEnabled"),
+        "creator_id": 0,
+        "namespace": {
+            "id": number(&namespace["id"])?,
+            "path": namespace["path"],
+            "name": namespace["name"],
+            "kind": namespace_kind,
+            "full_path": full_path,
+            "web_url": format!("{}/{}", web_url.trim_end_matches('/'), full_path),
+        },
+        "avatar_url": node["avatarUrl"],
+        "star_count": node["starCount"].as_u64().unwrap_or(0),
+        "forks_count": node["forksCount"].as_u64().unwrap_or(0),
+        "open_issues_count": node["openIssuesCount"],
+        "public_jobs": enabled("publicJobs"),
+        "shared_with_groups": [],
+        "only_allow_merge_if_pipeline_succeeds": node["onlyAllowMergeIfPipelineSucceeds"],
+        "only_allow_merge_if_all_discussions_are_resolved": node["onlyAllowMergeIfAllDiscussionsAreResolved"],
+        "remove_source_branch_after_merge": node["removeSourceBranchAfterMerge"],
+        "printing_merge_request_link_enabled": node["printingMergeRequestLinkEnabled"],
+        "request_access_enabled": enabled("requestAccessEnabled"),
+        "statistics": statistics,
+    });
+    // the object is split, so the macro doesn't reach its recursion limit
+    let features = json!({
+        "jobs_enabled": enabled("jobsEnabled"),
+        "issues_enabled": enabled("issuesEnabled"),
+        "merge_requests_enabled": enabled("mergeRequestsEnabled"),
+        "snippets_enabled": enabled("snippetsEnabled"),
+        "wiki_enabled": enabled("wikiEnabled"),
+        "builds_access_level": access_level("jobsEnabled"),
+        "issues_access_level": access_level("issuesEnabled"),
+        "merge_requests_access_level": access_level("mergeRequestsEnabled"),
+        "repository_access_level": "enabled",
+        "snippets_access_level": access_level("snippetsEnabled"),
+        "wiki_access_level": access_level("wikiEnabled"),
+    });
+    if let (Value::Object(project), Value::Object(features)) = (&mut project, features) {
+        project.extend(features);
+    }
+    serde_json::from_value(project).map_err(|e| {
+        Error::Graphql(format!(
+            "invalid project {}: {}",
+            node["fullPath"].as_str().unwrap_or_default(),
+            e
+        ))
+    })
+}
+
+/// Parses the response of [`PROJECTS_QUERY`], `web_url` is the URL of the instance
+pub(crate) fn parse_projects(resp: Value, web_url: &str) -> Result<ProjectsPage> {
+    let resp: Response = serde_json::from_value(resp).map_err(|e| Error::Graphql(e.to_string()))?;
+    if let Some(errors) = resp.errors.filter(|e| !e.is_empty()) {
+        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+        return Err(Error::Graphql(messages.join(", ")));
+    }
+    let connection = resp
+        .data
+        .ok_or_else(|| Error::Graphql("no data in the response".to_string()))?
+        .projects;
+    let projects = connection
+        .nodes
+        .iter()
+        .map(|node| rest_project(node, web_url))
+        .collect::<Result<Vec<_>>>()?;
+    let next = if connection.page_info.has_next_page {
+        connection.page_info.end_cursor
+    } else {
+        None
+    };
+    Ok(ProjectsPage { projects, next })
+}
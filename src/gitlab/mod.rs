@@ -1,6 +1,9 @@
-pub use client::Client;
+pub use client::{Auth, Client};
 
 pub mod client;
+mod graphql;
 mod macros;
+pub mod oauth;
 
 pub mod types;
+pub mod version;
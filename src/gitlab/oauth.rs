@@ -0,0 +1,295 @@
+//! OAuth 2.0 login to a GitLab instance with the device authorization grant.
+//!
+//! The refresh token is stored in a file readable only by the user, so next runs log in
+//! without the browser. Access tokens are refreshed in the background before they expire.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::error::{Error, Result};
+use crate::http::REDACTED;
+
+/// Scopes of the API and git transfers
+pub const DEFAULT_SCOPES: &str = "api read_repository write_repository";
+const DEVICE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// GitLab access tokens live for 2 hours, they're refreshed in advance
+const DEFAULT_EXPIRES_IN: u64 = 7200;
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A code which the user enters on the verification page to authorize the login
+#[derive(Debug, Deserialize)]
+pub struct DeviceCode {
+    device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Refresh tokens by instance URLs and application ids
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TokenStore {
+    tokens: BTreeMap<String, String>,
+}
+
+/// The default file of stored refresh tokens: `$XDG_CONFIG_HOME/gitlobster/oauth.json`
+/// or `~/.config/gitlobster/oauth.json`
+pub fn default_store() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("gitlobster").join("oauth.json"))
+}
+
+fn read_store(path: &PathBuf) -> TokenStore {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn write_store(path: &PathBuf, store: &TokenStore) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(
+        serde_json::to_string_pretty(store)
+            .unwrap_or_default()
+            .as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// An OAuth session of the application (by its id) on the GitLab instance
+pub struct Session {
+    http: reqwest::Client,
+    url: Url,
+    client_id: String,
+    store: Option<PathBuf>,
+    access_token: RwLock<String>,
+    refresh_token: RwLock<Option<String>>,
+}
+
+/// Tokens are redacted, so they don't get to logs and errors
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("url", &self.url)
+            .field("client_id", &self.client_id)
+            .field("store", &self.store)
+            .field("access_token", &REDACTED)
+            .field("refresh_token", &REDACTED)
+            .finish()
+    }
+}
+
+impl Session {
+    /// Logs in with the stored refresh token, or with the device authorization grant if there's
+    /// no valid one, `on_code` shows the code to the user. The returned session refreshes
+    /// its access token until it's dropped
+    pub async fn login(
+        http: reqwest::Client,
+        url: Url,
+        client_id: String,
+        store: Option<PathBuf>,
+        on_code: impl Fn(&DeviceCode),
+    ) -> Result<Arc<Self>> {
+        let session = Self {
+            http,
+            url,
+            client_id,
+            store,
+            access_token: RwLock::new(String::new()),
+            refresh_token: RwLock::new(None),
+        };
+        let token = match session.stored_refresh_token() {
+            Some(refresh_token) => match session.refresh(&refresh_token).await {
+                Ok(token) => Some(token),
+                Err(e) => {
+                    warn!("the stored OAuth refresh token isn't valid: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let token = match token {
+            Some(token) => token,
+            None => session.authorize_device(on_code).await?,
+        };
+        let expires_in = session.update(token)?;
+
+        let session = Arc::new(session);
+        tokio::spawn(refresh_periodically(Arc::downgrade(&session), expires_in));
+        Ok(session)
+    }
+
+    /// The current access token
+    pub fn access_token(&self) -> String {
+        self.access_token.read().unwrap().clone()
+    }
+
+    fn store_key(&self) -> String {
+        format!("{} {}", self.url, self.client_id)
+    }
+
+    fn stored_refresh_token(&self) -> Option<String> {
+        let store = read_store(self.store.as_ref()?);
+        store.tokens.get(&self.store_key()).cloned()
+    }
+
+    /// Saves the token, returns when it expires
+    fn update(&self, token: TokenResponse) -> Result<Duration> {
+        *self.access_token.write().unwrap() = token.access_token;
+        if let Some(refresh_token) = token.refresh_token {
+            if let Some(path) = &self.store {
+                let mut store = read_store(path);
+                store.tokens.insert(self.store_key(), refresh_token.clone());
+                write_store(path, &store)?;
+            }
+            *self.refresh_token.write().unwrap() = Some(refresh_token);
+        }
+        Ok(Duration::from_secs(
+            token.expires_in.unwrap_or(DEFAULT_EXPIRES_IN),
+        ))
+    }
+
+    async fn post_token(
+        &self,
+        form: &[(&str, &str)],
+    ) -> Result<std::result::Result<TokenResponse, ErrorResponse>> {
+        let resp = self
+            .http
+            .post(self.url.join("oauth/token")?)
+            .form(form)
+            .send()
+            .await?;
+        if resp.status().is_success() {
+            Ok(Ok(resp.json().await?))
+        } else {
+            let status = resp.status();
+            resp.json()
+                .await
+                .map(Err)
+                .map_err(|_| Error::Auth(format!("OAuth token request failed with {}", status)))
+        }
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse> {
+        self.post_token(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", &self.client_id),
+        ])
+        .await?
+        .map_err(|e| oauth_error(&e))
+    }
+
+    async fn authorize_device(&self, on_code: impl Fn(&DeviceCode)) -> Result<TokenResponse> {
+        let resp = self
+            .http
+            .post(self.url.join("oauth/authorize_device")?)
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("scope", DEFAULT_SCOPES),
+            ])
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            return Err(match resp.json::<ErrorResponse>().await {
+                Ok(e) => oauth_error(&e),
+                Err(_) => Error::Auth(format!("OAuth device authorization failed with {}", status)),
+            });
+        }
+        let code: DeviceCode = resp.json().await?;
+        on_code(&code);
+
+        let mut interval = Duration::from_secs(code.interval.unwrap_or(5));
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(code.expires_in);
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(interval).await;
+            match self
+                .post_token(&[
+                    ("grant_type", DEVICE_GRANT_TYPE),
+                    ("device_code", &code.device_code),
+                    ("client_id", &self.client_id),
+                ])
+                .await?
+            {
+                Ok(token) => {
+                    info!("logged in with OAuth");
+                    return Ok(token);
+                }
+                Err(e) if e.error == "authorization_pending" => (),
+                Err(e) if e.error == "slow_down" => interval += Duration::from_secs(5),
+                Err(e) => return Err(oauth_error(&e)),
+            }
+        }
+        Err(Error::Auth("the OAuth device code has expired".to_string()))
+    }
+}
+
+fn oauth_error(e: &ErrorResponse) -> Error {
+    Error::Auth(format!(
+        "OAuth: {}",
+        e.error_description.as_ref().unwrap_or(&e.error)
+    ))
+}
+
+/// Refreshes the access token of the session before it expires while the session is alive
+async fn refresh_periodically(session: Weak<Session>, mut expires_in: Duration) {
+    loop {
+        tokio::time::sleep(
+            expires_in
+                .saturating_sub(REFRESH_MARGIN)
+                .max(MIN_REFRESH_INTERVAL),
+        )
+        .await;
+        let Some(session) = session.upgrade() else {
+            return;
+        };
+        let refresh_token = session.refresh_token.read().unwrap().clone();
+        let Some(refresh_token) = refresh_token else {
+            warn!("no OAuth refresh token, the access token can't be refreshed");
+            return;
+        };
+        match session.refresh(&refresh_token).await {
+            Ok(token) => match session.update(token) {
+                Ok(e) => expires_in = e,
+                Err(e) => warn!("failed to save the OAuth refresh token: {}", e),
+            },
+            Err(e) => {
+                warn!("failed to refresh the OAuth access token: {}", e);
+                expires_in = REFRESH_MARGIN + MIN_REFRESH_INTERVAL;
+            }
+        }
+    }
+}
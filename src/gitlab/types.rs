@@ -511,7 +511,9 @@ pub struct BasicProjectDetails {
 }
 
 /// Visibility levels of projects.
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum,
+)]
 pub enum VisibilityLevel {
     /// The project is visible to anonymous users.
     #[serde(rename = "public")]
@@ -611,6 +613,9 @@ pub struct Project {
     pub default_branch: Option<String>,
     /// A list of tags for the project.
     pub tag_list: Vec<String>,
+    /// A list of topics for the project.
+    #[serde(default)]
+    pub topics: Vec<String>,
     /// Whether the project is archived or not.
     pub archived: bool,
     /// Whether the project has an empty repository or not.
@@ -640,6 +645,9 @@ pub struct Project {
     /// This is supposed to be just `bool`, but projects created before the registry was
     /// supported appear to return `null`.
     pub container_registry_enabled: Option<bool>,
+    /// The prefix of container images of the project (`registry.example.com/group/project`).
+    #[serde(default)]
+    pub container_registry_image_prefix: Option<String>,
     /// When the repository was created.
     pub created_at: DateTime<Utc>,
     /// When the last activity on the project occurred.
@@ -751,6 +759,190 @@ pub struct ProjectStatistics {
     pub job_artifacts_size: u64,
 }
 
+/// States of a project export.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportStatus {
+    /// The export isn't scheduled.
+    #[serde(rename = "none")]
+    None,
+    /// The export is waiting for a worker.
+    #[serde(rename = "queued")]
+    Queued,
+    /// The export is in progress.
+    #[serde(rename = "started")]
+    Started,
+    /// The archive is ready for downloading.
+    #[serde(rename = "finished")]
+    Finished,
+    /// A new archive is being made to replace a finished one.
+    #[serde(rename = "regeneration_in_progress")]
+    RegenerationInProgress,
+    /// The export failed.
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+/// States of a project import.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStatus {
+    /// The project isn't imported.
+    #[serde(rename = "none")]
+    None,
+    /// The import is waiting for a worker.
+    #[serde(rename = "scheduled")]
+    Scheduled,
+    /// The import is in progress.
+    #[serde(rename = "started")]
+    Started,
+    /// The project is imported.
+    #[serde(rename = "finished")]
+    Finished,
+    /// The import failed.
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+/// Status of a project import.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectImport {
+    /// The ID of the project.
+    pub id: ProjectId,
+    /// The path to the project's repository with its namespace.
+    pub path_with_namespace: String,
+    /// The state of the import.
+    pub import_status: ImportStatus,
+    /// Description of error if project failed to import.
+    pub import_error: Option<String>,
+}
+
+/// Status of a project export.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectExport {
+    /// The ID of the project.
+    pub id: ProjectId,
+    /// The state of the export.
+    pub export_status: ExportStatus,
+}
+
+/// The version of the instance.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerVersion {
+    /// The version, e.g. `16.11.2-ee`.
+    pub version: String,
+    /// The revision of the version.
+    pub revision: String,
+}
+
+/// An access token, as returned for the token of the request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersonalAccessToken {
+    /// The name of the token.
+    pub name: String,
+    /// Scopes of the token.
+    pub scopes: Vec<String>,
+    /// When the token expires.
+    pub expires_at: Option<NaiveDate>,
+    /// Whether the token is active.
+    pub active: bool,
+    /// Whether the token is revoked.
+    pub revoked: bool,
+}
+
+/// A tag of a container registry repository.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegistryTag {
+    /// The name of the tag.
+    pub name: String,
+    /// The path of the image with the tag.
+    pub path: String,
+    /// The location of the image with the tag (`registry.example.com/group/project:tag`).
+    pub location: String,
+}
+
+/// A container registry repository of a project.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegistryRepository {
+    /// The ID of the repository.
+    pub id: u64,
+    /// The name of the repository inside the project, empty for the root image.
+    pub name: String,
+    /// The path of the repository.
+    pub path: String,
+    /// The location of the repository (`registry.example.com/group/project/name`).
+    pub location: String,
+    /// Tags of the repository.
+    #[serde(default)]
+    pub tags: Vec<RegistryTag>,
+}
+
+/// A badge of a project or a group.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Badge {
+    /// The ID of the badge.
+    pub id: u64,
+    /// The name of the badge.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The link URL, it may contain placeholders (`%{project_path}`, `%{default_branch}`, ...).
+    pub link_url: String,
+    /// The image URL, it may contain placeholders.
+    pub image_url: String,
+    /// The kind of the badge (`project` or `group`).
+    pub kind: String,
+}
+
+/// A deploy key of a project.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeployKey {
+    /// The ID of the key.
+    pub id: u64,
+    /// The title of the key.
+    pub title: String,
+    /// The public key.
+    pub key: String,
+    /// The fingerprint of the key.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Whether the key can push to the project.
+    #[serde(default)]
+    pub can_push: bool,
+    /// When the key was created.
+    pub created_at: DateTime<Utc>,
+    /// When the key expires.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A file of a package in the package registry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PackageFile {
+    /// The ID of the file.
+    pub id: u64,
+    /// The name of the file.
+    pub file_name: String,
+    /// The size of the file.
+    pub size: u64,
+    /// The SHA256 checksum of the file.
+    #[serde(default)]
+    pub file_sha256: Option<String>,
+}
+
+/// A package in the package registry of a project.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Package {
+    /// The ID of the package.
+    pub id: u64,
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub version: String,
+    /// The type of the package (`generic`, `maven`, `npm`, `pypi`, ...).
+    pub package_type: String,
+    /// Files of the package, they aren't returned in lists of packages.
+    #[serde(default)]
+    pub package_files: Vec<PackageFile>,
+}
+
 /// Access levels for groups and projects.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AccessLevel {
@@ -1026,6 +1218,25 @@ pub struct RepoBranch {
 pub struct PRBAccessLevel {
     pub access_level: u64,
     pub access_level_description: String,
+    /// The user of a user-based access level
+    #[serde(default)]
+    pub user_id: Option<u64>,
+    /// The group of a group-based access level
+    #[serde(default)]
+    pub group_id: Option<u64>,
+}
+
+impl PRBAccessLevel {
+    /// The most permissive role-based access level of the list, `0` (no one) if there are
+    /// only user-based and group-based levels
+    pub fn role_level(levels: &[PRBAccessLevel]) -> u64 {
+        levels
+            .iter()
+            .filter(|l| l.user_id.is_none() && l.group_id.is_none())
+            .map(|l| l.access_level)
+            .min()
+            .unwrap_or(0)
+    }
 }
 
 /// A protected branch on a repository
@@ -1035,6 +1246,8 @@ pub struct ProtectedRepoBranch {
     pub push_access_levels: Vec<PRBAccessLevel>,
     pub merge_access_levels: Vec<PRBAccessLevel>,
     pub code_owner_approval_required: Option<bool>,
+    #[serde(default)]
+    pub allow_force_push: bool,
 }
 
 /// The ID of a git object.
@@ -1196,6 +1409,10 @@ pub struct ProjectSnippet {
     pub expires_at: Option<DateTime<Utc>>,
     /// The URL of the snippet.
     pub web_url: String,
+    /// The URL to clone the snippet repository over HTTPS.
+    pub http_url_to_repo: Option<String>,
+    /// The URL to clone the snippet repository over SSH.
+    pub ssh_url_to_repo: Option<String>,
 }
 
 // This is just used as a common "base class" in Ruby.
@@ -2768,20 +2985,15 @@ pub struct Pipeline {
     pub detailed_status: Value,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub enum PipelineVariableType {
     #[serde(rename = "env_var")]
+    #[default]
     EnvVar,
     #[serde(rename = "file")]
     File,
 }
 
-impl Default for PipelineVariableType {
-    fn default() -> Self {
-        PipelineVariableType::EnvVar
-    }
-}
-
 /// A pipeline variable.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PipelineVariable {
@@ -0,0 +1,44 @@
+//! Versions of GitLab instances, features which need newer versions than the instance's one
+//! are skipped or use older endpoints
+
+use std::fmt::{self, Display, Formatter};
+
+/// The `major.minor` version of GitLab
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parses the version of `/version`, e.g. `16.11.2-ee`
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.split(['.', '-']);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some(Self::new(major, minor))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Older instances are warned about, some requests may fail on them
+pub const MIN_SUPPORTED: Version = Version::new(13, 0);
+/// `/projects/:id/integrations`, older instances have `/projects/:id/services`
+pub const INTEGRATIONS: Version = Version::new(14, 4);
+/// `/personal_access_tokens/self`
+pub const CURRENT_TOKEN: Version = Version::new(15, 5);
+/// Keyset pagination of `/projects`
+pub const KEYSET_PAGINATION: Version = Version::new(13, 0);
+/// Fields of projects in GraphQL which are used to list them
+pub const GRAPHQL_PROJECTS: Version = Version::new(15, 0);
+/// The package registry in all tiers
+pub const PACKAGES: Version = Version::new(13, 3);
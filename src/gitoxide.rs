@@ -0,0 +1,152 @@
+//! Fetching bare mirrors with gitoxide, a pure Rust implementation of git,
+//! so the git executable isn't needed for cloning
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use gix::bstr::BStr;
+use gix::progress::Discard;
+use gix::refs::transaction::{Change, PreviousValue, RefEdit, RefLog};
+use gix::remote::fetch::RefMap;
+use gix::remote::Direction;
+
+use crate::error::{Error, Result};
+
+const REMOTE: &str = "upstream";
+/// Mirrors have all refs of the upstream
+const MIRROR_REFSPEC: &str = "+refs/*:refs/*";
+
+fn git_error(e: impl Display) -> Error {
+    Error::Git(e.to_string())
+}
+
+/// Refs aren't filtered on the remote by prefixes of refspecs, `refs/*` doesn't match
+/// branches as a prefix
+fn ref_map_options() -> gix::remote::ref_map::Options {
+    gix::remote::ref_map::Options {
+        prefix_from_spec_as_filter_on_remote: false,
+        ..Default::default()
+    }
+}
+
+/// Interrupts the blocking fetch when its future is dropped, e.g. a cancelled project
+struct InterruptOnDrop(Arc<AtomicBool>);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Clones the bare mirror of the repository, or fetches all refs of it to the existing one
+/// and deletes refs which are deleted upstream, like `git fetch --prune`.
+/// The fetch is interrupted after the timeout
+pub async fn fetch_mirror(src: String, dst: String, timeout: Option<Duration>) -> Result<()> {
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let _interrupt_on_drop = InterruptOnDrop(interrupt.clone());
+    let fetch = tokio::task::spawn_blocking(move || fetch_mirror_blocking(&src, &dst, &interrupt));
+    let fetched = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fetch)
+            .await
+            .map_err(|_| Error::Timeout(format!("gitoxide didn't finish in {:?}", timeout)))?,
+        None => fetch.await,
+    };
+    fetched.map_err(git_error)?
+}
+
+fn fetch_mirror_blocking(src: &str, dst: &str, interrupt: &AtomicBool) -> Result<()> {
+    match gix::open(dst) {
+        Ok(repo) => {
+            // a remote by the URL, so credentials of the current run are used
+            let remote = repo
+                .remote_at(src)
+                .and_then(|r| r.with_refspecs([MIRROR_REFSPEC], Direction::Fetch))
+                .map_err(git_error)?;
+            let outcome = remote
+                .connect(Direction::Fetch)
+                .and_then(|c| c.prepare_fetch(Discard, ref_map_options()))
+                .and_then(|f| f.receive(Discard, interrupt))
+                .map_err(git_error)?;
+            prune_refs(&repo, &outcome.ref_map)?;
+        }
+        // the partial clone is removed if the clone fails
+        Err(_) => {
+            if let Some(parent) = Path::new(dst).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            gix::prepare_clone_bare(src, dst)
+                .and_then(|c| c.with_remote_name(REMOTE))
+                .map(|c| c.with_fetch_options(ref_map_options()))
+                .map(|c| {
+                    c.configure_remote(|mut r| {
+                        r.replace_refspecs([MIRROR_REFSPEC], Direction::Fetch)?;
+                        Ok(r)
+                    })
+                })
+                .and_then(|mut c| c.fetch_only(Discard, interrupt))
+                .map_err(git_error)?;
+        }
+    }
+    // refs are packed, but empty ref directories are removed,
+    // git doesn't recognize repositories without `refs/`
+    std::fs::create_dir_all(Path::new(dst).join("refs").join("heads"))?;
+    std::fs::create_dir_all(Path::new(dst).join("refs").join("tags"))?;
+    Ok(())
+}
+
+/// Deletes local refs which aren't fetched from the remote anymore
+fn prune_refs(repo: &gix::Repository, ref_map: &RefMap) -> Result<()> {
+    let fetched: HashSet<&BStr> = ref_map
+        .mappings
+        .iter()
+        .filter_map(|m| m.local.as_ref().map(|l| l.as_ref()))
+        .collect();
+    let mut edits = vec![];
+    for r in repo
+        .references()
+        .map_err(git_error)?
+        .all()
+        .map_err(git_error)?
+    {
+        let r = r.map_err(git_error)?;
+        let name = r.name().as_bstr();
+        if name.starts_with(b"refs/") && !fetched.contains(name) {
+            edits.push(RefEdit {
+                change: Change::Delete {
+                    expected: PreviousValue::Any,
+                    log: RefLog::AndReference,
+                },
+                name: r.name().to_owned(),
+                deref: false,
+            });
+        }
+    }
+    repo.edit_references(edits).map_err(git_error)?;
+    Ok(())
+}
+
+/// Returns object ids of all local branches and tags by ref names
+pub async fn refs(path: String) -> Result<BTreeMap<String, String>> {
+    tokio::task::spawn_blocking(move || refs_blocking(&path))
+        .await
+        .map_err(git_error)?
+}
+
+fn refs_blocking(path: &str) -> Result<BTreeMap<String, String>> {
+    let repo = gix::open(path).map_err(git_error)?;
+    let platform = repo.references().map_err(git_error)?;
+    let mut refs = BTreeMap::new();
+    for prefix in ["refs/heads/", "refs/tags/"] {
+        for r in platform.prefixed(prefix).map_err(git_error)? {
+            let r = r.map_err(git_error)?;
+            if let Some(id) = r.target().try_id() {
+                refs.insert(r.name().as_bstr().to_string(), id.to_string());
+            }
+        }
+    }
+    Ok(refs)
+}
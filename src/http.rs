@@ -0,0 +1,173 @@
+//! Settings of the HTTP client for API requests
+
+use std::time::Duration;
+use std::{env, fs};
+
+use reqwest::header::HeaderValue;
+use url::Url;
+
+use crate::error::{Error, Result};
+use crate::git;
+use crate::throttle::Throttle;
+
+/// Environment variables with the proxy for all protocols, like curl (and git) uses them
+const ALL_PROXY_VARS: [&str; 2] = ["ALL_PROXY", "all_proxy"];
+
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    /// The timeout of a request, including the transfer of its body (e.g. an export archive)
+    pub timeout: Option<Duration>,
+    /// A proxy (`http://`, `https://`, `socks5://` or `socks5h://`) for API requests and git
+    /// transfers over HTTP(S), `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` are used without it
+    pub proxy: Option<Url>,
+    /// A PEM file with CA certificates trusted in addition to the system ones,
+    /// git uses it instead of its default bundle
+    pub ca_cert: Option<String>,
+    /// Don't verify TLS certificates
+    pub insecure: bool,
+    /// PEM files with the client certificate and its (PKCS#8) key for TLS client authentication
+    pub client_cert: Option<(String, String)>,
+}
+
+/// Shown instead of secrets in `Debug` output
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// Adds the header with a secret (a token) to the request, the value is marked sensitive,
+/// so it's hidden in `Debug` output of requests
+pub(crate) fn secret_header(
+    req: reqwest::RequestBuilder,
+    name: &str,
+    value: &str,
+) -> reqwest::RequestBuilder {
+    match HeaderValue::from_str(value) {
+        Ok(mut value) => {
+            value.set_sensitive(true);
+            req.header(name, value)
+        }
+        // the request fails with the invalid value when it's sent
+        Err(_) => req.header(name, value),
+    }
+}
+
+/// Compares secrets in constant time, so the time doesn't tell how much of a guess matches
+pub fn secrets_equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns the value of the first set environment variable
+pub(crate) fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|v| !v.is_empty()))
+}
+
+impl HttpOptions {
+    /// Builds the HTTP client, it can be shared by API clients of a run
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+        } else if let Some(proxy) = env_var(&ALL_PROXY_VARS) {
+            // `HTTPS_PROXY` and `HTTP_PROXY` are used by reqwest itself, they take precedence
+            if env_var(&["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]).is_none() {
+                builder = builder
+                    .proxy(reqwest::Proxy::all(proxy)?.no_proxy(reqwest::NoProxy::from_env()));
+            }
+        }
+        if let Some(file) = &self.ca_cert {
+            for cert in read_certificates(file)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if self.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        if let Some((cert, key)) = &self.client_cert {
+            let identity = reqwest::Identity::from_pkcs8_pem(&fs::read(cert)?, &fs::read(key)?)
+                .map_err(|e| Error::Params(format!("invalid client certificate: {}", e)))?;
+            builder = builder.identity(identity);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// The proxy for a git transfer to the host, like curl chooses it
+    pub(crate) fn git_proxy(&self, https: bool, host: &str) -> Result<Option<Url>> {
+        if let Some(proxy) = &self.proxy {
+            return Ok(Some(proxy.clone()));
+        }
+        let vars: &[&str] = if https {
+            &["HTTPS_PROXY", "https_proxy"]
+        } else {
+            &["http_proxy", "HTTP_PROXY"]
+        };
+        let Some(proxy) = env_var(vars).or_else(|| env_var(&ALL_PROXY_VARS)) else {
+            return Ok(None);
+        };
+        if no_proxy(host) {
+            return Ok(None);
+        }
+        // curl allows proxies without a scheme, they are HTTP ones
+        let proxy = if proxy.contains("://") {
+            proxy
+        } else {
+            format!("http://{}", proxy)
+        };
+        Ok(Some(Url::parse(&proxy)?))
+    }
+
+    /// Configures git transfers like API requests, with the bandwidth limit they're routed
+    /// through a local proxy which runs while the returned value is kept
+    pub(crate) async fn configure_git(
+        &self,
+        bandwidth_limit: Option<u64>,
+    ) -> Result<Option<Throttle>> {
+        // the proxy URL can have credentials
+        git::set_secret_config("http.proxy", self.proxy.as_ref().map(Url::to_string));
+        // the variables override `http.ssl*` options of git configs
+        git::set_env("GIT_SSL_CAINFO", self.ca_cert.clone());
+        git::set_env("GIT_SSL_NO_VERIFY", self.insecure.then(|| "1".to_string()));
+        let (cert, key) = self.client_cert.clone().unzip();
+        git::set_env("GIT_SSL_CERT", cert);
+        git::set_env("GIT_SSL_KEY", key);
+        match bandwidth_limit {
+            Some(limit) => Ok(Some(Throttle::start(limit, self.clone()).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads all certificates of the PEM bundle
+fn read_certificates(file: &str) -> Result<Vec<reqwest::Certificate>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    let pem = fs::read_to_string(file)?;
+    let certs = pem
+        .match_indices(BEGIN)
+        .map(|(start, _)| {
+            let end = pem[start + BEGIN.len()..]
+                .find(BEGIN)
+                .map_or(pem.len(), |i| start + BEGIN.len() + i);
+            reqwest::Certificate::from_pem(&pem.as_bytes()[start..end])
+                .map_err(|e| Error::Params(format!("invalid CA certificate in {}: {}", file, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(Error::Params(format!("no CA certificates in {}", file)));
+    }
+    Ok(certs)
+}
+
+/// Checks that the host is excluded from proxying by `NO_PROXY`
+fn no_proxy(host: &str) -> bool {
+    let Some(list) = env_var(&["NO_PROXY", "no_proxy"]) else {
+        return false;
+    };
+    list.split(',').map(str::trim).any(|pattern| {
+        let pattern = pattern.trim_start_matches("*.").trim_start_matches('.');
+        pattern == "*"
+            || (!pattern.is_empty()
+                && (host == pattern || host.ends_with(&format!(".{}", pattern))))
+    })
+}
@@ -0,0 +1,23 @@
+//! A library for cloning all available repositories in a GitLab instance
+//!
+//! The entry point is the [`cloner`] module, the `gitlobster` binary is a thin wrapper around it.
+
+mod backup;
+pub mod cloner;
+pub mod error;
+mod git;
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+#[cfg(feature = "gitoxide")]
+mod gitoxide;
+pub mod http;
+pub mod limiter;
+mod registry;
+pub mod restore;
+pub mod retry;
+pub mod shutdown;
+pub mod state;
+mod throttle;
+
+pub use error::{Error, Result};
@@ -0,0 +1,279 @@
+//! Limiting the rate of API requests and the bandwidth of git transfers
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::error::{Error, Result};
+
+/// Spaces requests evenly to keep their rate, it's shared by all clients of a run
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    /// When the next request can be sent
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the turn of a request, waiting requests are served in order
+    pub async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = Instant::now().max(*next) + self.interval;
+    }
+}
+
+/// API requests, their total latency, rate limited requests and failed attempts
+/// since the last adjustment of the adaptive concurrency
+static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static LATENCY_MICROS: AtomicU64 = AtomicU64::new(0);
+static THROTTLED: AtomicUsize = AtomicUsize::new(0);
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the latency of an API request, `throttled` if it's rate limited (429)
+pub(crate) fn record_request(latency: Duration, throttled: bool) {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+    LATENCY_MICROS.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    if throttled {
+        THROTTLED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records a failed attempt of an API request or a git operation
+pub(crate) fn record_failure() {
+    FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The load of instances observed since the last adjustment
+#[derive(Debug, Clone, Copy, Default)]
+struct Load {
+    latency: Option<Duration>,
+    throttled: usize,
+    failures: usize,
+}
+
+fn take_load() -> Load {
+    let requests = REQUESTS.swap(0, Ordering::Relaxed);
+    let micros = LATENCY_MICROS.swap(0, Ordering::Relaxed);
+    Load {
+        latency: (requests > 0).then(|| Duration::from_micros(micros / requests as u64)),
+        throttled: THROTTLED.swap(0, Ordering::Relaxed),
+        failures: FAILURES.swap(0, Ordering::Relaxed),
+    }
+}
+
+/// The concurrency starts low and grows while instances keep up
+const ADAPTIVE_START: usize = 2;
+/// The latency grown by the factor over the lowest one means the instance is overloaded
+const LATENCY_FACTOR: u32 = 2;
+
+/// Concurrency adjusted by the observed load: it grows by one while requests are fast
+/// and succeed, it's halved on rate limited requests and failures, and it's decreased by one
+/// when the latency grows
+#[derive(Debug)]
+pub struct AdaptiveConcurrency {
+    limit: usize,
+    max: usize,
+    /// The lowest average latency of API requests
+    baseline: Option<Duration>,
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(max: usize) -> Self {
+        take_load();
+        Self {
+            limit: ADAPTIVE_START.min(max).max(1),
+            max: max.max(1),
+            baseline: None,
+        }
+    }
+
+    /// How many operations run at once
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Adjusts the concurrency after a batch of operations, `failed` operations are counted
+    /// as failures in addition to failed attempts
+    pub fn update(&mut self, failed: usize) -> usize {
+        let load = take_load();
+        let slow = match (load.latency, self.baseline) {
+            (Some(latency), Some(baseline)) => latency > baseline * LATENCY_FACTOR,
+            _ => false,
+        };
+        if let Some(latency) = load.latency {
+            self.baseline = Some(self.baseline.map_or(latency, |b| b.min(latency)));
+        }
+        self.limit = if load.throttled > 0 || load.failures + failed > 0 {
+            self.limit / 2
+        } else if slow {
+            self.limit - 1
+        } else {
+            self.limit + 1
+        }
+        .clamp(1, self.max);
+        self.limit
+    }
+}
+
+/// Paces transferred bytes to keep the bandwidth, it's shared by all transfers of a run
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    bytes_per_second: u64,
+    /// When the next chunk can be transferred
+    next: Mutex<Instant>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the turn of a transferred chunk of the size
+    pub async fn consume(&self, bytes: usize) {
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = Instant::now().max(*next)
+            + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+    }
+}
+
+/// Parses a bandwidth in bytes per second like `10MiB/s`, `500KB/s` or `1048576`,
+/// `K`, `M` and `G` are decimal units and `Ki`, `Mi` and `Gi` are binary ones
+pub fn parse_bandwidth(s: &str) -> Result<u64> {
+    let value = s.trim();
+    let bytes = parse_bytes(value.strip_suffix("/s").unwrap_or(value))
+        .map_err(|_| Error::Params(format!("invalid bandwidth: {}", s)))?;
+    if bytes == 0 {
+        return Err(Error::Params(format!("invalid bandwidth: {}", s)));
+    }
+    Ok(bytes)
+}
+
+/// Parses a size in bytes like `2GiB`, `500MB` or `1048576`, units are the same as in bandwidths
+pub fn parse_size(s: &str) -> Result<u64> {
+    parse_bytes(s).map_err(|_| Error::Params(format!("invalid size: {}", s)))
+}
+
+fn parse_bytes(s: &str) -> Result<u64> {
+    let invalid = || Error::Params(format!("invalid size: {}", s));
+    let value = s.trim();
+    let value = value.strip_suffix('B').unwrap_or(value);
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier = match unit.trim() {
+        "" => 1,
+        "K" | "k" => 1_000,
+        "Ki" => 1 << 10,
+        "M" => 1_000_000,
+        "Mi" => 1 << 20,
+        "G" => 1_000_000_000,
+        "Gi" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The load is recorded in statics, so tests of the adaptive concurrency don't run at once
+    static LOAD: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn parse_bandwidth_units() {
+        assert_eq!(parse_bandwidth("10MiB/s").unwrap(), 10 << 20);
+        assert_eq!(parse_bandwidth("500KB/s").unwrap(), 500_000);
+        assert_eq!(parse_bandwidth("1.5G/s").unwrap(), 1_500_000_000);
+        assert_eq!(parse_bandwidth(" 1048576 ").unwrap(), 1_048_576);
+    }
+
+    #[test]
+    fn parse_bandwidth_invalid() {
+        for s in ["0", "0KB/s", "", "fast", "10XB/s", "-5MB/s", "MB/s"] {
+            assert!(matches!(parse_bandwidth(s), Err(Error::Params(_))), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("1048576").unwrap(), 1_048_576);
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert!(matches!(parse_size("2 TB"), Err(Error::Params(_))));
+        assert!(matches!(parse_size("big"), Err(Error::Params(_))));
+    }
+
+    #[test]
+    fn adaptive_grows_and_clamps_to_max() {
+        let _load = LOAD.lock().unwrap();
+        let mut concurrency = AdaptiveConcurrency::new(4);
+        assert_eq!(concurrency.limit(), 2);
+        record_request(ms(10), false);
+        assert_eq!(concurrency.update(0), 3);
+        assert_eq!(concurrency.update(0), 4);
+        assert_eq!(concurrency.update(0), 4);
+        assert_eq!(concurrency.limit(), 4);
+
+        assert_eq!(AdaptiveConcurrency::new(1).limit(), 1);
+        assert_eq!(AdaptiveConcurrency::new(0).update(0), 1);
+    }
+
+    #[test]
+    fn adaptive_halves_on_throttling_and_failures() {
+        let _load = LOAD.lock().unwrap();
+        let mut concurrency = AdaptiveConcurrency::new(16);
+        for _ in 0..6 {
+            concurrency.update(0);
+        }
+        assert_eq!(concurrency.limit(), 8);
+        record_request(ms(10), true);
+        assert_eq!(concurrency.update(0), 4);
+        record_failure();
+        assert_eq!(concurrency.update(0), 2);
+        assert_eq!(concurrency.update(1), 1);
+        record_failure();
+        assert_eq!(concurrency.update(0), 1);
+    }
+
+    #[test]
+    fn adaptive_decrements_on_latency() {
+        let _load = LOAD.lock().unwrap();
+        let mut concurrency = AdaptiveConcurrency::new(16);
+        record_request(ms(10), false);
+        assert_eq!(concurrency.update(0), 3);
+        record_request(ms(20), false);
+        assert_eq!(concurrency.update(0), 4);
+        record_request(ms(30), false);
+        record_request(ms(40), false);
+        assert_eq!(concurrency.update(0), 3);
+        // the lowest latency stays the baseline
+        record_request(ms(25), false);
+        assert_eq!(concurrency.update(0), 2);
+        record_request(ms(5), false);
+        assert_eq!(concurrency.update(0), 3);
+        record_request(ms(15), false);
+        assert_eq!(concurrency.update(0), 2);
+    }
+}
@@ -0,0 +1,144 @@
+//! `listen`: an HTTP listener of GitLab webhooks, push and tag push events queue
+//! their projects to sync
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use gitlobster::http::secrets_equal;
+use gitlobster::shutdown::Shutdown;
+use hyper::body::HttpBody;
+use hyper::header::CONTENT_LENGTH;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, info, warn};
+
+/// GitLab sends the secret token of the webhook in the header
+const TOKEN_HEADER: &str = "X-Gitlab-Token";
+/// Kinds of events which change repositories, of project hooks and system hooks
+const SYNCED_EVENTS: [&str; 2] = ["push", "tag_push"];
+/// Larger payloads are rejected, GitLab includes at most 20 commits in push events
+const MAX_BODY_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct Event {
+    object_kind: String,
+    project_id: Option<u64>,
+    project: Option<EventProject>,
+}
+
+#[derive(Deserialize)]
+struct EventProject {
+    path_with_namespace: String,
+}
+
+struct Listener {
+    secret: Option<String>,
+    /// Ids of projects to sync
+    queue: UnboundedSender<String>,
+}
+
+impl Listener {
+    fn handle(&self, method: &Method, token: Option<&[u8]>, body: &[u8]) -> StatusCode {
+        if method != Method::POST {
+            return StatusCode::METHOD_NOT_ALLOWED;
+        }
+        if let Some(secret) = &self.secret {
+            if !token.is_some_and(|t| secrets_equal(t, secret.as_bytes())) {
+                warn!("a webhook with a wrong secret token is rejected");
+                return StatusCode::UNAUTHORIZED;
+            }
+        }
+        let event: Event = match serde_json::from_slice(body) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("a webhook with a bad payload is rejected: {}", e);
+                return StatusCode::BAD_REQUEST;
+            }
+        };
+        if !SYNCED_EVENTS.contains(&event.object_kind.as_str()) {
+            debug!("the {} webhook is ignored", event.object_kind);
+            return StatusCode::NO_CONTENT;
+        }
+        let path = event.project.map(|p| p.path_with_namespace);
+        let Some(project) = event.project_id.map(|id| id.to_string()).or(path) else {
+            warn!(
+                "the {} webhook has no project, it's ignored",
+                event.object_kind
+            );
+            return StatusCode::BAD_REQUEST;
+        };
+        info!("the {} webhook of {} is queued", event.object_kind, project);
+        match self.queue.send(project) {
+            Ok(()) => StatusCode::ACCEPTED,
+            Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Reads the body up to [`MAX_BODY_SIZE`], the status of the error otherwise
+async fn read_body(mut body: Body, length: Option<usize>) -> Result<Vec<u8>, StatusCode> {
+    if length.is_some_and(|l| l > MAX_BODY_SIZE) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let mut read = Vec::with_capacity(length.unwrap_or_default());
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if read.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        read.extend_from_slice(&chunk);
+    }
+    Ok(read)
+}
+
+async fn respond(
+    listener: Arc<Listener>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse().ok());
+    let status = match read_body(body, length).await {
+        Ok(body) => {
+            let token = parts.headers.get(TOKEN_HEADER).map(|t| t.as_bytes());
+            listener.handle(&parts.method, token, &body)
+        }
+        Err(status) => {
+            warn!("a webhook is rejected: {}", status);
+            status
+        }
+    };
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+    Ok(resp)
+}
+
+/// Answers webhooks at once, projects are synced by the receiver of the queue;
+/// the listener stops with the run
+pub async fn serve(
+    addr: SocketAddr,
+    secret: Option<String>,
+    queue: UnboundedSender<String>,
+    shutdown: Arc<Shutdown>,
+) -> Result<()> {
+    let listener = Arc::new(Listener { secret, queue });
+    let make_service = make_service_fn(move |_| {
+        let listener = listener.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| respond(listener.clone(), req))) }
+    });
+    let server = Server::try_bind(&addr)
+        .with_context(|| format!("Failed to listen on {}", addr))?
+        .serve(make_service);
+    info!("listening for webhooks on {}", server.local_addr());
+    server
+        .with_graceful_shutdown(async move { shutdown.stopped().await })
+        .await
+        .context("The webhook listener failed")
+}
@@ -0,0 +1,158 @@
+//! `--log-file`: logs of scheduled runs written to a file which is rotated by its size
+//! or by time, rotated files are `<path>.1` (the newest), `<path>.2`, ...
+
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+
+/// When the log file is rotated besides its size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogRotation {
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl LogRotation {
+    /// The period of the time, logs of another period go to a new file
+    fn period(self, time: DateTime<Local>) -> Option<String> {
+        match self {
+            LogRotation::Never => None,
+            LogRotation::Hourly => Some(time.format("%Y-%m-%d %H").to_string()),
+            LogRotation::Daily => Some(time.format("%Y-%m-%d").to_string()),
+        }
+    }
+}
+
+/// How often writes check that the file is still at the path
+const MOVED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    rotation: LogRotation,
+    /// Rotated files which are kept
+    keep: usize,
+    file: File,
+    size: u64,
+    period: Option<String>,
+    checked: Instant,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Whether both metadata are of the same file
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &Metadata, _: &Metadata) -> bool {
+    true
+}
+
+impl RotatingFile {
+    /// Logs are appended to the file, it's rotated first if it's from a previous period.
+    /// The file stays open across runs of `daemon`: it's rotated by writes and reopened
+    /// when something else (e.g. logrotate) moves or deletes it
+    pub fn open(
+        path: PathBuf,
+        max_size: Option<u64>,
+        rotation: LogRotation,
+        keep: usize,
+    ) -> Result<Self> {
+        let file = open_append(&path)
+            .with_context(|| format!("Failed to open the log file {}", path.display()))?;
+        let meta = file.metadata()?;
+        let period = rotation.period(Local::now());
+        let mut log = Self {
+            file,
+            size: meta.len(),
+            period: period.clone(),
+            path: path.clone(),
+            max_size,
+            rotation,
+            keep,
+            checked: Instant::now(),
+        };
+        let modified = meta.modified().map(DateTime::<Local>::from);
+        let stale = modified.is_ok_and(|m| rotation.period(m) != period);
+        if log.size > 0 && stale {
+            log.rotate()
+                .with_context(|| format!("Failed to rotate the log file {}", path.display()))?;
+        }
+        Ok(log)
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Opens the file at the path again if it isn't the open file anymore
+    fn reopen_moved(&mut self) -> io::Result<()> {
+        let moved = match fs::metadata(&self.path) {
+            Ok(meta) => !same_file(&meta, &self.file.metadata()?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e),
+        };
+        if moved {
+            self.file.flush()?;
+            self.file = open_append(&self.path)?;
+            self.size = self.file.metadata()?.len();
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.checked.elapsed() >= MOVED_CHECK_INTERVAL {
+            self.reopen_moved()?;
+            self.checked = Instant::now();
+        }
+        let period = self.rotation.period(Local::now());
+        let full = self
+            .max_size
+            .is_some_and(|max| self.size + buf.len() as u64 > max);
+        if self.size > 0 && (full || period != self.period) {
+            self.rotate()?;
+        }
+        self.period = period;
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,9 +1,19 @@
 mod cli;
-mod cloner;
-mod git;
-mod gitlab;
-use anyhow::Result;
+mod config;
+mod events;
+mod listen;
+mod logfile;
+mod tokens;
+mod tui;
+use std::process::ExitCode;
 
-fn main() -> Result<()> {
-    cli::run()
+#[tokio::main]
+async fn main() -> ExitCode {
+    match cli::run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(cli::exit_code(&e))
+        }
+    }
 }
@@ -0,0 +1,106 @@
+//! Copying container images with skopeo
+
+use crate::error::{Error, Result};
+use base64::Engine;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::from_utf8;
+use tokio::process::Command;
+use tracing::info;
+
+/// A temporary auth file of skopeo with credentials (`<username>:<token>`) of the registry
+/// of the reference, credentials in arguments would be visible in the process list.
+/// Only the user can read it, it's removed when it's dropped
+struct AuthFile(PathBuf);
+
+impl AuthFile {
+    /// `None` for references without a registry, e.g. archives
+    fn new(reference: &str, creds: &str) -> Result<Option<Self>> {
+        let Some(registry) = reference
+            .strip_prefix("docker://")
+            .and_then(|r| r.split('/').next())
+        else {
+            return Ok(None);
+        };
+        let mut name = [0u8; 16];
+        getrandom::getrandom(&mut name)
+            .map_err(|e| Error::Registry(format!("failed to name the auth file: {}", e)))?;
+        let name: String = name.iter().map(|b| format!("{:02x}", b)).collect();
+        let path = std::env::temp_dir().join(format!("gitlobster-auth-{}.json", name));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path)?;
+        let file_path = Self(path);
+        let auth = base64::engine::general_purpose::STANDARD.encode(creds);
+        let content = serde_json::json!({ "auths": { registry: { "auth": auth } } });
+        file.write_all(content.to_string().as_bytes())?;
+        Ok(Some(file_path))
+    }
+
+    fn path(&self) -> String {
+        self.0.display().to_string()
+    }
+}
+
+impl Drop for AuthFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+async fn skopeo(args: Vec<String>) -> Result<String> {
+    info!("skopeo {}", args.join(" "));
+
+    let cmd = Command::new("skopeo").args(args).output().await?;
+
+    let errmsg = from_utf8(&cmd.stderr).unwrap_or_default();
+    if !cmd.status.success() {
+        return Err(Error::Registry(errmsg.to_string()));
+    }
+
+    let out = from_utf8(&cmd.stdout).map_err(|e| Error::Registry(e.to_string()))?;
+    Ok(out.to_string())
+}
+
+/// Checks that skopeo is installed
+pub async fn installed() -> bool {
+    skopeo(vec!["--version".to_string()]).await.is_ok()
+}
+
+/// Copies the image between references (`docker://<location>:<tag>`,
+/// `oci-archive:<file>`), credentials are `<username>:<token>`
+pub async fn copy(
+    src: &str,
+    dst: &str,
+    src_creds: &Option<String>,
+    dst_creds: &Option<String>,
+) -> Result<()> {
+    let mut args = vec!["copy".to_string()];
+    // an archive keeps a single image, so other platforms are copied only between registries
+    if dst.starts_with("docker://") {
+        args.push("--all".to_string());
+    }
+    let src_auth = src_creds
+        .as_deref()
+        .map(|creds| AuthFile::new(src, creds))
+        .transpose()?
+        .flatten();
+    if let Some(auth) = &src_auth {
+        args.push(format!("--src-authfile={}", auth.path()));
+    }
+    let dst_auth = dst_creds
+        .as_deref()
+        .map(|creds| AuthFile::new(dst, creds))
+        .transpose()?
+        .flatten();
+    if let Some(auth) = &dst_auth {
+        args.push(format!("--dest-authfile={}", auth.path()));
+    }
+    args.push(src.to_string());
+    args.push(dst.to_string());
+
+    skopeo(args).await.map(|_| ())
+}
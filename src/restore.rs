@@ -0,0 +1,404 @@
+//! Restoring a local tree of cloned projects (or export archives) to a GitLab instance
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::try_join_all;
+use serde::de::DeserializeOwned;
+use tracing::{error, info, warn};
+use url::Url;
+
+use crate::backup::Repo;
+use crate::cloner::{
+    make_git_path, sibling_url, HostKeyChecking, DEFAULT_CONCURRENCY_LIMIT, DESIGN_SUFFIX,
+    LABELS_EXT, MILESTONES_EXT, PACKAGES_SUFFIX, REGISTRY_SUFFIX, RELEASES_SUFFIX, SNIPPETS_SUFFIX,
+    TRASH_DIR, WIKI_SUFFIX,
+};
+use crate::error::{Error, Result};
+use crate::gitlab::types;
+use crate::http::HttpOptions;
+use crate::limiter::RateLimiter;
+use crate::retry::Retry;
+use crate::{git, gitlab};
+
+const ARCHIVE_EXT: &str = ".tar.gz";
+const MIRROR_EXT: &str = ".git";
+const IMPORT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const IMPORT_MAX_POLLS: usize = 720;
+
+#[derive(Debug)]
+pub struct TargetGitlabOptions {
+    url: Url,
+    token: String,
+}
+
+impl TargetGitlabOptions {
+    pub fn new(url: String, token: String) -> Result<Self> {
+        let url = Url::parse(&url)?;
+        Ok(Self { url, token })
+    }
+}
+
+pub struct RestoreParams {
+    pub target: TargetGitlabOptions,
+    /// The local directory with cloned projects (or export archives)
+    pub src: String,
+    /// A group on the target GitLab, projects are restored to their original paths inside it
+    pub group: Option<String>,
+    /// Import export archives (`<src>/<path>.tar.gz`) instead of pushing repositories
+    pub archives: bool,
+    /// Create labels and milestones of `<src>/<path>.labels.json` and `<src>/<path>.milestones.json`
+    pub with_labels: bool,
+    pub concurrency_limit: usize,
+    pub upload_ssh: bool,
+    /// The key, the port and options of ssh for uploads
+    pub upload_ssh_options: git::SshOptions,
+    /// Retries of failed API requests and pushes
+    pub retry: Retry,
+    /// Limits the rate of API requests
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Limits the bandwidth of pushes over HTTP(S) (all of them together) in bytes per second
+    pub bandwidth_limit: Option<u64>,
+    /// Settings of the HTTP client for API requests, e.g. the timeout
+    pub http: HttpOptions,
+    /// Git commands running longer are killed, the project isn't restored
+    pub git_timeout: Option<Duration>,
+    /// Pass tokens to git with a credential helper instead of embedding them into remote URLs
+    pub git_credential_helper: bool,
+    /// The git executable, `git` from `PATH` if it isn't set
+    pub git_path: Option<String>,
+    /// Arguments passed to all git commands before the command name, e.g. `-c protocol.version=2`
+    pub git_args: Vec<String>,
+    /// The policy of ssh for unknown host keys, the default of ssh if it isn't set
+    pub ssh_host_key_checking: Option<HostKeyChecking>,
+    /// The known hosts file of ssh, `~/.ssh/known_hosts` if it isn't set
+    pub known_hosts: Option<String>,
+}
+
+impl RestoreParams {
+    pub fn new(target: TargetGitlabOptions, src: String) -> Self {
+        Self {
+            target,
+            src,
+            group: None,
+            archives: false,
+            with_labels: false,
+            concurrency_limit: DEFAULT_CONCURRENCY_LIMIT,
+            upload_ssh: false,
+            upload_ssh_options: Default::default(),
+            retry: Retry::default(),
+            rate_limiter: None,
+            bandwidth_limit: None,
+            http: HttpOptions::default(),
+            git_timeout: None,
+            git_credential_helper: false,
+            git_path: None,
+            git_args: vec![],
+            ssh_host_key_checking: None,
+            known_hosts: None,
+        }
+    }
+}
+
+/// A project found in the local directory
+#[derive(Debug, Clone)]
+pub struct LocalProject {
+    /// The project path with namespaces (`group/subgroup/project`)
+    pub path: String,
+    /// A local repository (a working clone or a bare mirror) or an export archive
+    pub file: String,
+}
+
+fn is_repo(dir: &Path) -> bool {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.join(".git").exists() || (name.ends_with(MIRROR_EXT) && dir.join("HEAD").is_file())
+}
+
+fn find_in_dir(root: &Path, dir: &Path, archives: bool, out: &mut Vec<LocalProject>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let file = entry?.path();
+        let rel = file
+            .strip_prefix(root)
+            .map_err(|e| Error::Params(e.to_string()))?
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+
+        let path = if file.is_dir() {
+            if rel == TRASH_DIR
+                || rel.ends_with(SNIPPETS_SUFFIX)
+                || rel.ends_with(RELEASES_SUFFIX)
+                || rel.ends_with(REGISTRY_SUFFIX)
+                || rel.ends_with(PACKAGES_SUFFIX)
+                || rel
+                    .strip_suffix(MIRROR_EXT)
+                    .unwrap_or(&rel)
+                    .ends_with(DESIGN_SUFFIX)
+            {
+                continue;
+            }
+            if !is_repo(&file) {
+                find_in_dir(root, &file, archives, out)?;
+                continue;
+            }
+            if archives {
+                continue;
+            }
+            rel.strip_suffix(MIRROR_EXT).unwrap_or(&rel).to_string()
+        } else if let Some(path) = rel.strip_suffix(ARCHIVE_EXT).filter(|_| archives) {
+            path.to_string()
+        } else {
+            continue;
+        };
+
+        out.push(LocalProject {
+            path,
+            file: file.to_string_lossy().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Finds all projects to restore in the local directory
+pub fn find(p: &RestoreParams) -> Result<Vec<LocalProject>> {
+    let root = Path::new(&p.src);
+    let mut projects = vec![];
+    find_in_dir(root, root, p.archives, &mut projects)?;
+    projects.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(projects)
+}
+
+async fn import_archive(
+    client: &gitlab::Client,
+    namespace: &str,
+    name: &str,
+    project: &LocalProject,
+) -> Result<()> {
+    let mut import = client
+        .import_project(name.to_string(), namespace.to_string(), &project.file)
+        .await?;
+
+    for _ in 0..IMPORT_MAX_POLLS {
+        match import.import_status {
+            types::ImportStatus::Finished => return Ok(()),
+            types::ImportStatus::Failed => {
+                return Err(Error::Export(format!(
+                    "import of {} failed: {}",
+                    &project.path,
+                    import.import_error.unwrap_or_default()
+                )))
+            }
+            _ => {}
+        }
+        tokio::time::sleep(IMPORT_POLL_INTERVAL).await;
+        import = client.get_import(import.id).await?;
+    }
+
+    Err(Error::Export(format!(
+        "import of {} timed out",
+        &project.path
+    )))
+}
+
+/// Where a local project is restored to
+enum Target {
+    /// Push to the created (or existing) project
+    Push(Repo),
+    /// Import the archive to a namespace as a project with the name
+    Import { namespace: String, name: String },
+}
+
+/// Creates the namespace and the project on the target GitLab, they are made sequentially
+/// because concurrent requests can create the same group twice
+async fn make_target(
+    client: &gitlab::Client,
+    group: &Option<types::Group>,
+    project: &LocalProject,
+    archives: bool,
+) -> Result<Option<Target>> {
+    let mut path: Vec<String> = project.path.split('/').map(str::to_string).collect();
+    let name = path.pop().expect("invalid project path");
+    let full_path = match &group {
+        Some(gr) => format!("{}/{}", gr.full_path, &project.path),
+        None => project.path.clone(),
+    };
+    if let Some(base) = project.path.strip_suffix(WIKI_SUFFIX) {
+        let base_path = full_path.strip_suffix(WIKI_SUFFIX).unwrap_or(base);
+        return match client.project_exist(base_path.to_string()).await? {
+            Some(target) => Ok(Some(Target::Push(Repo {
+                http_url: sibling_url(&target.http_url_to_repo, WIKI_SUFFIX),
+                ssh_url: sibling_url(&target.ssh_url_to_repo, WIKI_SUFFIX),
+                project_id: None,
+                registry: None,
+            }))),
+            None => {
+                warn!("project of wiki {} isn't found, skipped", &project.path);
+                Ok(None)
+            }
+        };
+    }
+
+    let exist = client.project_exist(full_path).await?;
+
+    if archives {
+        if exist.is_some() {
+            warn!("project {} already exists, skipped", &project.path);
+            return Ok(None);
+        }
+        let (namespace, _) = client.make_namespace(path, &[], group).await?;
+        return Ok(Some(Target::Import { namespace, name }));
+    }
+
+    let target = match exist {
+        Some(target) => target,
+        None => {
+            let (_, parent_id) = client.make_namespace(path, &[], group).await?;
+            let parent_id = parent_id.ok_or_else(|| {
+                Error::Params(format!("parent group for project {} not found", &name))
+            })?;
+            client
+                .make_project(name.clone(), name, parent_id, None)
+                .await?
+        }
+    };
+    Ok(Some(Target::Push(Repo {
+        http_url: target.http_url_to_repo,
+        ssh_url: target.ssh_url_to_repo,
+        project_id: Some(target.id),
+        registry: target.container_registry_image_prefix,
+    })))
+}
+
+/// Reads a JSON file exported with the project, `None` if it doesn't exist
+fn read_json<T: DeserializeOwned>(
+    p: &RestoreParams,
+    project: &LocalProject,
+    ext: &str,
+) -> Result<Option<T>> {
+    let file = Path::new(&p.src).join(format!("{}{}", &project.path, ext));
+    if !file.is_file() {
+        return Ok(None);
+    }
+    let data = fs::read(file)?;
+    let value = serde_json::from_slice(&data).map_err(std::io::Error::from)?;
+    Ok(Some(value))
+}
+
+/// Creates exported labels and milestones on the restored project
+async fn restore_labels(
+    client: &gitlab::Client,
+    p: &RestoreParams,
+    project: &LocalProject,
+    id: types::ProjectId,
+) -> Result<()> {
+    let labels: Vec<types::Label> = read_json(p, project, LABELS_EXT)?.unwrap_or_default();
+    for label in &labels {
+        client.set_label(id, label).await?;
+    }
+    let milestones: Vec<types::Milestone> =
+        read_json(p, project, MILESTONES_EXT)?.unwrap_or_default();
+    for milestone in &milestones {
+        client.set_milestone(id, milestone).await?;
+    }
+    Ok(())
+}
+
+async fn restore_project(
+    client: &gitlab::Client,
+    p: &RestoreParams,
+    project: &LocalProject,
+    target: &Target,
+    git_http_auth: &Option<String>,
+) -> Result<()> {
+    info!("restoring {}", &project.path);
+
+    match target {
+        Target::Import { namespace, name } => {
+            import_archive(client, namespace, name, project).await
+        }
+        Target::Push(repo) => {
+            let remote = make_git_path(
+                &repo.http_url,
+                &repo.ssh_url,
+                git_http_auth,
+                &p.upload_ssh_options,
+                git::SshRole::Push,
+            );
+            p.retry
+                .run(&format!("push of {}", &project.path), || {
+                    git::push_all(&project.file, &remote)
+                })
+                .await?;
+            match repo.project_id {
+                Some(id) if p.with_labels => restore_labels(client, p, project, id).await,
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+/// Creates groups and projects of the local directory on the target GitLab and pushes
+/// all branches and tags to them (or imports export archives), returns restored projects.
+/// Wiki clones (`<path>.wiki`) are pushed to wikis of restored projects, snippets and designs are skipped
+pub async fn restore(p: RestoreParams) -> Result<Vec<LocalProject>> {
+    let client = gitlab::Client::new(p.target.token.as_str(), p.target.url.clone(), None, true)?
+        .with_http(p.http.client()?)
+        .with_retry(p.retry)
+        .with_limiter(p.rate_limiter.clone())
+        .detect_version()
+        .await;
+    client
+        .check_token(
+            "target",
+            &[("restoring projects", gitlab::client::API_SCOPES)],
+        )
+        .await?;
+    git::set_timeout(p.git_timeout);
+    git::set_credential_helper(p.git_credential_helper);
+    git::set_executable(p.git_path.clone(), p.git_args.clone());
+    git::set_ssh_host_keys(
+        p.ssh_host_key_checking.map(|c| c.as_str()),
+        p.known_hosts.as_deref(),
+    );
+    let _throttle = p.http.configure_git(p.bandwidth_limit).await?;
+    let group = match &p.group {
+        Some(gr) => Some(client.get_group(gr.clone()).await?),
+        None => None,
+    };
+    let git_http_auth = if p.upload_ssh || p.archives {
+        None
+    } else {
+        let user = client.get_current_user().await?;
+        Some(format!("{}:{}", user.username, &p.target.token))
+    };
+
+    let mut targets = vec![];
+    for project in find(&p)? {
+        if let Some(target) = make_target(&client, &group, &project, p.archives).await? {
+            targets.push((project, target));
+        }
+    }
+
+    let mut restored = vec![];
+    for chunk in targets.chunks(p.concurrency_limit) {
+        let results = try_join_all(chunk.iter().map(|(pr, target)| async {
+            match restore_project(&client, &p, pr, target, &git_http_auth).await {
+                // a stuck project (or one needing a prompt) doesn't stop restoring the others
+                Err(e) if e.is_timeout() || e.is_prompt() => {
+                    error!("failed to restore {}: {}", &pr.path, e);
+                    Ok(false)
+                }
+                result => result.map(|_| true),
+            }
+        }))
+        .await?;
+        for ((pr, _), ok) in chunk.iter().zip(results) {
+            if ok {
+                restored.push(pr.clone());
+            }
+        }
+    }
+
+    Ok(restored)
+}
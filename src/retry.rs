@@ -0,0 +1,261 @@
+//! Retrying transient failures of API requests and git operations with exponential backoff
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use tracing::warn;
+
+use crate::error::Result;
+use crate::limiter::{self, RateLimiter};
+
+pub const DEFAULT_RETRIES: usize = 3;
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// Rate limited requests are retried without counting them as failures, but not forever
+const MAX_RATE_LIMIT_WAITS: usize = 100;
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(3600);
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+}
+
+/// How long to wait until the rate limit is reset by `Retry-After` (seconds or a date),
+/// or by the reset time of `RateLimit-Reset` (GitLab) or `X-RateLimit-Reset` (GitHub)
+fn rate_limit_wait(headers: &HeaderMap) -> Option<Duration> {
+    let now = Utc::now();
+    let until = |date: DateTime<Utc>| (date - now).to_std().unwrap_or_default();
+    let wait = if let Some(value) = header(headers, RETRY_AFTER.as_str()) {
+        match value.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => until(DateTime::parse_from_rfc2822(value).ok()?.into()),
+        }
+    } else {
+        let reset = header(headers, "ratelimit-reset")
+            .or_else(|| header(headers, "x-ratelimit-reset"))?
+            .parse::<i64>()
+            .ok()?;
+        until(DateTime::from_timestamp(reset, 0)?)
+    };
+    Some(wait.min(MAX_RATE_LIMIT_WAIT))
+}
+
+/// Checks that the response used the last request allowed by the rate limit
+fn rate_limit_exhausted(headers: &HeaderMap) -> bool {
+    header(headers, "ratelimit-remaining")
+        .or_else(|| header(headers, "x-ratelimit-remaining"))
+        .is_some_and(|v| v == "0")
+}
+
+/// How failed operations are retried
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// Attempts after the first failure
+    pub retries: usize,
+    /// The delay before the first retry, it's doubled for every next one
+    pub delay: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+}
+
+impl Retry {
+    /// Operations are attempted once
+    pub const NONE: Retry = Retry {
+        retries: 0,
+        delay: Duration::ZERO,
+    };
+
+    /// The delay before the retry with the number (from 0), a random half of it is jitter
+    /// to spread retries of concurrent operations
+    fn backoff(&self, attempt: usize) -> Duration {
+        let delay = self
+            .delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(MAX_RETRY_DELAY);
+        let half = delay.as_millis() as u64 / 2;
+        let jitter = RandomState::new().build_hasher().finish() % (half + 1);
+        Duration::from_millis(half + jitter)
+    }
+
+    /// Runs the operation until it succeeds, fails with a permanent error (e.g. denied access
+    /// or a rejected push) or retries are exhausted
+    pub async fn run<T, F, Fut>(&self, what: &str, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.retries && e.is_transient() => {
+                    limiter::record_failure();
+                    let backoff = self.backoff(attempt);
+                    let e = e.to_string();
+                    warn!("{} failed: {}, retrying in {:?}", what, e.trim(), backoff);
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends the request, connection errors, timeouts and 5xx responses are retried,
+    /// requests with non-idempotent methods (POST, PATCH) are retried only if they couldn't
+    /// connect, so they aren't done twice (e.g. creating a project).
+    /// Rate limited (429) requests are sent again after the limit is reset, and if the response
+    /// used the last allowed request, the next one is delayed until the reset.
+    /// Requests with streamed bodies can't be cloned, so they are sent once.
+    /// Every attempt waits for its turn of the limiter
+    pub async fn send(
+        &self,
+        req: RequestBuilder,
+        limiter: Option<&RateLimiter>,
+    ) -> reqwest::Result<Response> {
+        let idempotent = req
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .is_some_and(|r| r.method().is_idempotent());
+        let mut attempt = 0;
+        let mut waits = 0;
+        loop {
+            if let Some(limiter) = limiter {
+                limiter.wait().await;
+            }
+            let Some(next) = req.try_clone() else {
+                return req.send().await;
+            };
+            let started = Instant::now();
+            let result = next.send().await;
+            let throttled =
+                matches!(&result, Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS);
+            limiter::record_request(started.elapsed(), throttled);
+            let reason = match result {
+                Ok(resp)
+                    if resp.status() == StatusCode::TOO_MANY_REQUESTS
+                        && waits < MAX_RATE_LIMIT_WAITS =>
+                {
+                    let wait =
+                        rate_limit_wait(resp.headers()).unwrap_or_else(|| self.backoff(waits));
+                    warn!("rate limit is exceeded, waiting {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                    waits += 1;
+                    continue;
+                }
+                Ok(resp) if resp.status().is_success() && rate_limit_exhausted(resp.headers()) => {
+                    if let Some(wait) = rate_limit_wait(resp.headers()) {
+                        warn!("rate limit is reached, waiting {:?}", wait);
+                        tokio::time::sleep(wait).await;
+                    }
+                    return Ok(resp);
+                }
+                result if attempt >= self.retries => return result,
+                Ok(resp) if idempotent && resp.status().is_server_error() => {
+                    resp.status().to_string()
+                }
+                Err(e) if e.is_connect() => e.to_string(),
+                Err(e) if idempotent && (e.is_timeout() || e.is_request()) => e.to_string(),
+                result => return result,
+            };
+            limiter::record_failure();
+            let backoff = self.backoff(attempt);
+            warn!("request failed: {}, retrying in {:?}", reason, backoff);
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn assert_near(wait: Option<Duration>, secs: u64) {
+        let wait = wait.unwrap();
+        assert!(
+            wait <= Duration::from_secs(secs) && wait >= Duration::from_secs(secs - 2),
+            "{:?}",
+            wait
+        );
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        let wait = rate_limit_wait(&headers(&[("retry-after", "120".into())]));
+        assert_eq!(wait, Some(Duration::from_secs(120)));
+        let wait = rate_limit_wait(&headers(&[("retry-after", "86400".into())]));
+        assert_eq!(wait, Some(MAX_RATE_LIMIT_WAIT));
+    }
+
+    #[test]
+    fn retry_after_date() {
+        let date = (Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        assert_near(rate_limit_wait(&headers(&[("retry-after", date)])), 60);
+
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT".to_string();
+        let wait = rate_limit_wait(&headers(&[("retry-after", date)]));
+        assert_eq!(wait, Some(Duration::ZERO));
+
+        let wait = rate_limit_wait(&headers(&[("retry-after", "soon".into())]));
+        assert_eq!(wait, None);
+    }
+
+    #[test]
+    fn rate_limit_reset_timestamps() {
+        let reset = (Utc::now().timestamp() + 30).to_string();
+        assert_near(
+            rate_limit_wait(&headers(&[("ratelimit-reset", reset.clone())])),
+            30,
+        );
+        assert_near(
+            rate_limit_wait(&headers(&[("x-ratelimit-reset", reset.clone())])),
+            30,
+        );
+        // Retry-After is preferred
+        let wait = rate_limit_wait(&headers(&[
+            ("retry-after", "5".into()),
+            ("ratelimit-reset", reset),
+        ]));
+        assert_eq!(wait, Some(Duration::from_secs(5)));
+        assert_eq!(rate_limit_wait(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn rate_limit_remaining() {
+        assert!(rate_limit_exhausted(&headers(&[(
+            "ratelimit-remaining",
+            "0".into()
+        )])));
+        assert!(rate_limit_exhausted(&headers(&[(
+            "x-ratelimit-remaining",
+            "0".into()
+        )])));
+        assert!(!rate_limit_exhausted(&headers(&[(
+            "ratelimit-remaining",
+            "12".into()
+        )])));
+        assert!(!rate_limit_exhausted(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,79 @@
+//! Graceful shutdown of a run: after an interrupt no new projects are started, and projects
+//! in progress are cancelled after a grace period or by the next interrupt
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    /// Projects in progress finish until the grace period from the instant ends
+    Stopping(Instant),
+    Cancelled,
+}
+
+pub struct Shutdown {
+    state: watch::Sender<State>,
+    grace: Duration,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            state: watch::channel(State::Running).0,
+            grace,
+        }
+    }
+
+    /// The first interrupt stops the run, the next one cancels projects in progress,
+    /// returns true if they're cancelled
+    pub fn interrupt(&self) -> bool {
+        let mut cancelled = false;
+        self.state.send_modify(|state| {
+            *state = match state {
+                State::Running => State::Stopping(Instant::now()),
+                _ => {
+                    cancelled = true;
+                    State::Cancelled
+                }
+            }
+        });
+        cancelled
+    }
+
+    /// Checks that no new projects are started
+    pub fn is_stopping(&self) -> bool {
+        *self.state.borrow() != State::Running
+    }
+
+    /// Waits until the run is stopped
+    pub async fn stopped(&self) {
+        let mut state = self.state.subscribe();
+        while *state.borrow_and_update() == State::Running {
+            let _ = state.changed().await;
+        }
+    }
+
+    /// Waits until projects in progress are cancelled
+    pub(crate) async fn cancelled(&self) {
+        let mut state = self.state.subscribe();
+        loop {
+            let current = *state.borrow_and_update();
+            match current {
+                State::Cancelled => return,
+                State::Stopping(at) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(at + self.grace) => return,
+                        _ = state.changed() => {}
+                    }
+                }
+                State::Running => {
+                    // the sender lives as long as the shutdown
+                    let _ = state.changed().await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Sync history of projects kept between runs in `<dst>/.gitlobster/state.db`
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::info;
+
+use crate::error::Result;
+use crate::gitlab::types;
+
+/// A directory inside the local directory with the sync state
+pub const STATE_DIR: &str = ".gitlobster";
+const STATE_FILE: &str = "state.db";
+/// GitLab updates `last_activity_at` of a project at most once in this interval,
+/// pushes soon after an update don't change it
+const ACTIVITY_THROTTLE_SECS: i64 = 3600;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS projects (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL,
+    last_activity_at TEXT,
+    refs TEXT NOT NULL DEFAULT '{}',
+    size INTEGER NOT NULL DEFAULT 0,
+    synced_at TEXT,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT
+);
+CREATE TABLE IF NOT EXISTS run_projects (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    position INTEGER NOT NULL,
+    project_id INTEGER NOT NULL,
+    project TEXT NOT NULL,
+    done INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (run_id, project_id)
+);
+";
+
+/// The recorded state of a project
+#[derive(Debug, Clone)]
+pub struct ProjectState {
+    pub id: types::ProjectId,
+    pub path: String,
+    /// `last_activity_at` of the project at the last successful sync
+    pub last_activity_at: Option<DateTime<Utc>>,
+    /// Object ids of branches and tags by ref names at the last successful sync
+    pub refs: BTreeMap<String, String>,
+    /// The size of the local clone in bytes
+    pub size: u64,
+    /// The start of the last successful sync, fetched refs are at least as new
+    pub synced_at: Option<DateTime<Utc>>,
+    /// Failed syncs since the last successful one
+    pub failures: u64,
+    pub last_error: Option<String>,
+}
+
+const SELECT_PROJECTS: &str =
+    "SELECT id, path, last_activity_at, refs, size, synced_at, failures, last_error FROM projects";
+
+fn project_state(row: &rusqlite::Row) -> rusqlite::Result<ProjectState> {
+    let refs: String = row.get(3)?;
+    Ok(ProjectState {
+        id: types::ProjectId::new(row.get::<_, i64>(0)? as u64),
+        path: row.get(1)?,
+        last_activity_at: row.get(2)?,
+        refs: serde_json::from_str(&refs).unwrap_or_default(),
+        size: row.get::<_, i64>(4)? as u64,
+        synced_at: row.get(5)?,
+        failures: row.get::<_, i64>(6)? as u64,
+        last_error: row.get(7)?,
+    })
+}
+
+pub struct State {
+    conn: Connection,
+    /// The current run, its projects are checkpointed to resume it if it's interrupted
+    run: Option<i64>,
+}
+
+impl State {
+    /// Opens (or creates) the state database of the local directory
+    pub fn open(dir: &str) -> Result<Self> {
+        fs::create_dir_all(format!("{}/{}", dir, STATE_DIR))?;
+        let conn = Connection::open(format!("{}/{}/{}", dir, STATE_DIR, STATE_FILE))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn, run: None })
+    }
+
+    /// Starts a new run of the projects
+    pub fn begin(&mut self, projects: &[types::Project]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (started_at) VALUES (?1)",
+            params![Utc::now()],
+        )?;
+        let run = tx.last_insert_rowid();
+        for (i, project) in projects.iter().enumerate() {
+            tx.execute(
+                "INSERT OR IGNORE INTO run_projects (run_id, position, project_id, project)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    run,
+                    i as i64,
+                    project.id.value() as i64,
+                    serde_json::to_string(project).unwrap_or_default(),
+                ],
+            )?;
+        }
+        tx.commit()?;
+        self.run = Some(run);
+        Ok(())
+    }
+
+    /// Continues the last run if it wasn't finished, returns its incomplete projects
+    pub fn resume(&mut self) -> Result<Option<Vec<types::Project>>> {
+        let run: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM runs WHERE finished_at IS NULL
+                AND id = (SELECT MAX(id) FROM runs)",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(run) = run else {
+            return Ok(None);
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT project FROM run_projects WHERE run_id = ?1 AND done = 0 ORDER BY position",
+        )?;
+        let projects = stmt
+            .query_map(params![run], |row| row.get::<_, String>(0))?
+            .filter_map(|p| p.map(|p| serde_json::from_str(&p).ok()).transpose())
+            .collect::<rusqlite::Result<Vec<types::Project>>>()?;
+        drop(stmt);
+        self.run = Some(run);
+        Ok(Some(projects))
+    }
+
+    /// Marks the project of the current run as complete (synced or skipped)
+    pub fn done(&self, project: &types::Project) -> Result<()> {
+        if let Some(run) = self.run {
+            self.conn.execute(
+                "UPDATE run_projects SET done = 1 WHERE run_id = ?1 AND project_id = ?2",
+                params![run, project.id.value() as i64],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the current run, it can't be resumed after that
+    pub fn finish(&self) -> Result<()> {
+        if let Some(run) = self.run {
+            self.conn.execute(
+                "UPDATE runs SET finished_at = ?1 WHERE id = ?2",
+                params![Utc::now(), run],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: types::ProjectId) -> Result<Option<ProjectState>> {
+        let state = self
+            .conn
+            .query_row(
+                &format!("{} WHERE id = ?1", SELECT_PROJECTS),
+                params![id.value() as i64],
+                project_state,
+            )
+            .optional()?;
+        Ok(state)
+    }
+
+    /// Returns recorded projects which aren't in the current run (deleted or moved out of scope)
+    pub fn stale(&self) -> Result<Vec<ProjectState>> {
+        let Some(run) = self.run else {
+            return Ok(vec![]);
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE id NOT IN (SELECT project_id FROM run_projects WHERE run_id = ?1) ORDER BY path",
+            SELECT_PROJECTS
+        ))?;
+        let projects = stmt
+            .query_map(params![run], project_state)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(projects)
+    }
+
+    /// Returns the number of recorded projects
+    pub fn known(&self) -> Result<usize> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM projects", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Returns stale projects which were in the previous run, i.e. disappeared since it
+    pub fn disappeared(&self) -> Result<Vec<ProjectState>> {
+        let Some(run) = self.run else {
+            return Ok(vec![]);
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE id NOT IN (SELECT project_id FROM run_projects WHERE run_id = ?1)
+            AND id IN (SELECT project_id FROM run_projects
+                WHERE run_id = (SELECT MAX(id) FROM runs WHERE id < ?1))
+            ORDER BY path",
+            SELECT_PROJECTS
+        ))?;
+        let projects = stmt
+            .query_map(params![run], project_state)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(projects)
+    }
+
+    /// Removes the project from the recorded projects
+    pub fn forget(&self, id: types::ProjectId) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM projects WHERE id = ?1",
+            params![id.value() as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Checks that the project was synced and hasn't changed (moved or got activity) since then,
+    /// projects synced within the throttle interval of their last activity are always changed,
+    /// as later pushes could leave the date as it is
+    pub fn unchanged(&self, project: &types::Project) -> Result<bool> {
+        let throttle = chrono::Duration::seconds(ACTIVITY_THROTTLE_SECS);
+        Ok(self.get(project.id)?.is_some_and(|s| {
+            s.failures == 0
+                && s.path == project.path_with_namespace
+                && s.last_activity_at == Some(project.last_activity_at)
+                && s.synced_at
+                    .is_some_and(|at| at - project.last_activity_at >= throttle)
+        }))
+    }
+
+    /// Records the successfully synced project with refs and the size of its clone,
+    /// `started` is the start of its fetch, returns the previous state of the project
+    pub fn synced(
+        &self,
+        project: &types::Project,
+        refs: &BTreeMap<String, String>,
+        size: u64,
+        started: DateTime<Utc>,
+    ) -> Result<Option<ProjectState>> {
+        let prev = self.get(project.id)?;
+        if let Some(prev) = &prev {
+            if prev.path != project.path_with_namespace {
+                info!(
+                    "{} was moved to {}",
+                    &prev.path, &project.path_with_namespace
+                );
+            }
+        }
+        self.conn.execute(
+            "INSERT INTO projects (id, path, last_activity_at, refs, size, synced_at, failures, last_error)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0, NULL)
+            ON CONFLICT (id) DO UPDATE SET path = ?2, last_activity_at = ?3, refs = ?4, size = ?5,
+                synced_at = ?6, failures = 0, last_error = NULL",
+            params![
+                project.id.value() as i64,
+                &project.path_with_namespace,
+                project.last_activity_at,
+                serde_json::to_string(refs).unwrap_or_default(),
+                size as i64,
+                started,
+            ],
+        )?;
+        self.done(project)?;
+        Ok(prev)
+    }
+
+    /// Records a failed sync of the project, its last successful sync is kept
+    pub fn failed(&self, project: &types::Project, error: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO projects (id, path, failures, last_error) VALUES (?1, ?2, 1, ?3)
+            ON CONFLICT (id) DO UPDATE SET path = ?2, failures = failures + 1, last_error = ?3",
+            params![
+                project.id.value() as i64,
+                &project.path_with_namespace,
+                error
+            ],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+//! A local HTTP proxy limiting the bandwidth of git transfers.
+//!
+//! Git runs as a separate process, so its transfers are throttled by proxying them:
+//! `http.proxy` of all git commands points to the proxy while it's running.
+//! HTTPS is tunneled with `CONNECT`, plain HTTP requests are forwarded one per connection.
+//! Connections go through the HTTP or SOCKS5 proxy of the transfers if there's one.
+//! Other local users could use the proxy (and credentials of the upstream one), so it accepts
+//! only requests with the random password of the run.
+//! Transfers over SSH aren't proxied.
+
+use std::sync::Arc;
+
+use base64::Engine;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::error::{Error, Result};
+use crate::git;
+use crate::http::{secrets_equal, HttpOptions};
+use crate::limiter::BandwidthLimiter;
+
+const PROXY_CONFIG: &str = "http.proxy";
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const CHUNK_SIZE: usize = 16 * 1024;
+/// The user of the proxy, its password is random for every run
+const PROXY_USER: &str = "gitlobster";
+const AUTH_REQUIRED: &[u8] = b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+    Proxy-Authenticate: Basic realm=\"gitlobster\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+pub struct Throttle {
+    task: JoinHandle<()>,
+}
+
+impl Throttle {
+    /// Starts the proxy with the bandwidth (bytes per second) shared by all transfers
+    pub async fn start(bytes_per_second: u64, http: HttpOptions) -> Result<Self> {
+        if let Some(proxy) = &http.proxy {
+            if !matches!(proxy.scheme(), "http" | "socks5" | "socks5h") {
+                return Err(Error::Params(format!(
+                    "{} proxies can't be used with the bandwidth limit",
+                    proxy.scheme()
+                )));
+            }
+        }
+        let http = Arc::new(http);
+        let mut password = [0u8; 16];
+        getrandom::getrandom(&mut password)
+            .map_err(|e| Error::Params(format!("failed to generate the proxy password: {}", e)))?;
+        let password: String = password.iter().map(|b| format!("{:02x}", b)).collect();
+        let auth = Arc::new(basic_auth(PROXY_USER, &password));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        info!(
+            "limiting git transfers to {} B/s via {}",
+            bytes_per_second, addr
+        );
+
+        let limiter = Arc::new(BandwidthLimiter::new(bytes_per_second));
+        let task = tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                let limiter = limiter.clone();
+                let http = http.clone();
+                let auth = auth.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = proxy(conn, &limiter, &http, &auth).await {
+                        debug!("proxied connection failed: {}", e);
+                    }
+                });
+            }
+        });
+        git::set_secret_config(
+            PROXY_CONFIG,
+            Some(format!("http://{}:{}@{}", PROXY_USER, password, addr)),
+        );
+        Ok(Self { task })
+    }
+}
+
+impl Drop for Throttle {
+    fn drop(&mut self) {
+        git::set_secret_config(PROXY_CONFIG, None);
+        self.task.abort();
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads the request head (up to the empty line), returns it with bytes read after it
+async fn read_head(conn: &mut TcpStream) -> io::Result<Option<(String, Vec<u8>)>> {
+    let mut buf = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        let n = conn.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            let head = String::from_utf8(buf).map_err(|_| invalid("invalid request head"))?;
+            return Ok(Some((head, rest)));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(invalid("too large request head"));
+        }
+    }
+}
+
+/// The value of the `Basic` authorization
+fn basic_auth(user: &str, password: &str) -> String {
+    let credentials = format!("{}:{}", user, password);
+    format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(credentials)
+    )
+}
+
+/// Values of the header in the request head
+fn header_values<'a>(head: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> {
+    head.split("\r\n").skip(1).filter_map(move |line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Removes lines of the header from the request head
+fn without_header(head: &str, name: &str) -> String {
+    head.split("\r\n")
+        .filter(|line| {
+            line.split_once(':')
+                .is_none_or(|(key, _)| !key.trim().eq_ignore_ascii_case(name))
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// Splits `host:port` of a `CONNECT` request, IPv6 addresses are in brackets
+fn host_port(target: &str) -> io::Result<(String, u16)> {
+    let (host, port) = target
+        .rsplit_once(':')
+        .ok_or_else(|| invalid("invalid request target"))?;
+    let port = port.parse().map_err(|_| invalid("invalid port"))?;
+    Ok((
+        host.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        port,
+    ))
+}
+
+/// Rewrites the proxy request to an origin one which closes the connection after the response
+fn origin_request(head: &str, target: &str, url: &Url) -> String {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path = format!("{}?{}", path, query);
+    }
+
+    let mut out = request_line.replacen(target, &path, 1) + "\r\n";
+    for line in lines.filter(|l| !l.is_empty()) {
+        let name = line.split(':').next().unwrap_or_default().to_lowercase();
+        if !matches!(
+            name.as_str(),
+            "connection" | "proxy-connection" | "proxy-authorization" | "keep-alive"
+        ) {
+            out += line;
+            out += "\r\n";
+        }
+    }
+    out + "Connection: close\r\n\r\n"
+}
+
+/// The user and the password of the proxy URL
+fn credentials(proxy: &Url) -> Option<(String, String)> {
+    if proxy.username().is_empty() {
+        return None;
+    }
+    let decode = |s: &str| {
+        urlencoding::decode(s)
+            .map(|s| s.into_owned())
+            .unwrap_or_default()
+    };
+    Some((
+        decode(proxy.username()),
+        decode(proxy.password().unwrap_or_default()),
+    ))
+}
+
+/// Passes the request head to the upstream HTTP proxy with its credentials
+/// instead of the ones of the local proxy
+fn upstream_request(head: &str, proxy: &Url) -> String {
+    let head = without_header(head.trim_end_matches("\r\n"), "proxy-authorization");
+    match credentials(proxy) {
+        Some((user, password)) => format!(
+            "{}\r\nProxy-Authorization: {}\r\n\r\n",
+            head,
+            basic_auth(&user, &password)
+        ),
+        None => format!("{}\r\n\r\n", head),
+    }
+}
+
+async fn connect_proxy(proxy: &Url) -> io::Result<TcpStream> {
+    let host = proxy.host_str().ok_or_else(|| invalid("no proxy host"))?;
+    let port = proxy.port_or_known_default().unwrap_or(1080);
+    TcpStream::connect((host, port)).await
+}
+
+/// Connects to the host through the SOCKS5 proxy, the host name is resolved by the proxy
+async fn socks5_connect(proxy: &Url, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut conn = connect_proxy(proxy).await?;
+    let credentials = credentials(proxy);
+    let method = if credentials.is_some() { 2 } else { 0 };
+    conn.write_all(&[5, 1, method]).await?;
+    let mut reply = [0; 2];
+    conn.read_exact(&mut reply).await?;
+    if reply != [5, method] {
+        return Err(invalid(
+            "the SOCKS5 proxy rejected the authentication method",
+        ));
+    }
+    if let Some((user, password)) = credentials {
+        let mut req = vec![1, user.len() as u8];
+        req.extend_from_slice(user.as_bytes());
+        req.push(password.len() as u8);
+        req.extend_from_slice(password.as_bytes());
+        conn.write_all(&req).await?;
+        conn.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(invalid("the SOCKS5 proxy rejected the credentials"));
+        }
+    }
+
+    let mut req = vec![5, 1, 0, 3, host.len() as u8];
+    req.extend_from_slice(host.as_bytes());
+    req.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&req).await?;
+    let mut reply = [0; 4];
+    conn.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(invalid("the SOCKS5 proxy failed to connect"));
+    }
+    // the bound address isn't needed
+    let addr_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => conn.read_u8().await? as usize,
+        _ => return Err(invalid("invalid SOCKS5 reply")),
+    };
+    let mut addr = vec![0; addr_len + 2];
+    conn.read_exact(&mut addr).await?;
+    Ok(conn)
+}
+
+async fn proxy(
+    mut client: TcpStream,
+    limiter: &BandwidthLimiter,
+    http: &HttpOptions,
+    auth: &str,
+) -> io::Result<()> {
+    let Some((head, rest)) = read_head(&mut client).await? else {
+        return Ok(());
+    };
+    // git sends the password after the challenge
+    let authorized = header_values(&head, "proxy-authorization")
+        .any(|value| secrets_equal(value.as_bytes(), auth.as_bytes()));
+    if !authorized {
+        return client.write_all(AUTH_REQUIRED).await;
+    }
+    let mut request_line = head.split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+
+    let connect = method == "CONNECT";
+    let url = if connect {
+        None
+    } else {
+        Some(Url::parse(target).map_err(|_| invalid("invalid request target"))?)
+    };
+    let (host, port) = match &url {
+        Some(url) => (
+            url.host_str()
+                .ok_or_else(|| invalid("no host"))?
+                .to_string(),
+            url.port_or_known_default().unwrap_or(80),
+        ),
+        None => host_port(target)?,
+    };
+    let upstream_proxy = http
+        .git_proxy(connect, &host)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let mut upstream = match (&upstream_proxy, &url) {
+        (Some(proxy), _) if proxy.scheme() == "http" => {
+            let mut upstream = connect_proxy(proxy).await?;
+            upstream
+                .write_all(upstream_request(&head, proxy).as_bytes())
+                .await?;
+            upstream
+        }
+        (upstream_proxy, url) => {
+            let mut upstream = match upstream_proxy {
+                Some(proxy) if proxy.scheme().starts_with("socks5") => {
+                    socks5_connect(proxy, &host, port).await?
+                }
+                Some(proxy) => {
+                    return Err(invalid(&format!("unsupported proxy {}", proxy.scheme())));
+                }
+                None => TcpStream::connect((host.as_str(), port)).await?,
+            };
+            match url {
+                Some(url) => {
+                    upstream
+                        .write_all(origin_request(&head, target, url).as_bytes())
+                        .await?
+                }
+                None => {
+                    client
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .await?
+                }
+            }
+            upstream
+        }
+    };
+    upstream.write_all(&rest).await?;
+
+    let (client_read, client_write) = client.into_split();
+    let (upstream_read, upstream_write) = upstream.into_split();
+    let upload = async {
+        copy(client_read, upstream_write, limiter).await?;
+        // The client has sent everything, but the response is still downloaded
+        std::future::pending().await
+    };
+    tokio::select! {
+        result = copy(upstream_read, client_write, limiter) => result,
+        result = upload => result,
+    }
+}
+
+/// Copies the stream paced by the limiter
+async fn copy(
+    mut from: impl AsyncRead + Unpin,
+    mut to: impl AsyncWrite + Unpin,
+    limiter: &BandwidthLimiter,
+) -> io::Result<()> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            return to.shutdown().await;
+        }
+        limiter.consume(n).await;
+        to.write_all(&buf[..n]).await?;
+    }
+}
@@ -0,0 +1,47 @@
+//! Tokens stored in the OS keyring (Secret Service, Keychain or Credential Manager)
+//! by instance URLs.
+
+use anyhow::{Context, Result};
+use keyring::Entry;
+use tracing::debug;
+use url::Url;
+
+const SERVICE: &str = "gitlobster";
+
+/// The instance URL in one form, so `https://gitlab.com` and `https://gitlab.com/` are one key
+fn key(url: &str) -> String {
+    Url::parse(url)
+        .map(|u| u.to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+fn entry(url: &str) -> Result<Entry> {
+    Entry::new(SERVICE, &key(url)).context("Failed to open the keyring")
+}
+
+/// Stores the token of the instance, it replaces a stored one
+pub fn save(url: &str, token: &str) -> Result<()> {
+    entry(url)?
+        .set_password(token)
+        .context("Failed to store the token in the keyring")
+}
+
+/// Removes the stored token of the instance, returns false if there's no one
+pub fn remove(url: &str) -> Result<bool> {
+    match entry(url)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).context("Failed to remove the token from the keyring"),
+    }
+}
+
+/// The stored token of the instance, a missing or unavailable keyring means no token
+pub fn find(url: &str) -> Option<String> {
+    match entry(url).and_then(|e| e.get_password().map_err(Into::into)) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            debug!("no token of {} in the keyring: {:#}", url, e);
+            None
+        }
+    }
+}
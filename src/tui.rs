@@ -0,0 +1,322 @@
+//! The `--tui` dashboard of a run: counters and throughput, a table of projects in progress,
+//! failed projects and recent log lines
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Stdout};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use gitlobster::cloner::{self, Progress};
+use gitlobster::shutdown::Shutdown;
+use indicatif::HumanBytes;
+use ratatui::crossterm::cursor::Show;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::prelude::CrosstermBackend;
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+
+use crate::cli;
+
+/// How often the dashboard is redrawn
+const TICK: Duration = Duration::from_millis(250);
+/// Log lines kept for the log pane
+const MAX_LOG_LINES: usize = 500;
+
+/// Log lines written while the dashboard is shown, the terminal is taken by it
+static LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+fn push_log(line: String) {
+    let mut logs = LOGS.lock().unwrap();
+    if logs.len() == MAX_LOG_LINES {
+        logs.pop_front();
+    }
+    logs.push_back(line);
+}
+
+/// Writes log lines to the log pane of the dashboard
+pub struct LogWriter;
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in String::from_utf8_lossy(buf).lines() {
+            if !line.trim().is_empty() {
+                push_log(line.to_string());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A project in progress
+struct Active {
+    stage: &'static str,
+    started: Instant,
+    /// The directory of the fetched project, its size is the received size
+    dir: Option<String>,
+    size: u64,
+}
+
+/// The state of the run shown by the dashboard
+pub struct Dashboard {
+    started: Instant,
+    total: usize,
+    done: usize,
+    /// Sizes of fetched projects which are done
+    received: u64,
+    active: BTreeMap<String, Active>,
+    failed: Vec<String>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            total: 0,
+            done: 0,
+            received: 0,
+            active: BTreeMap::new(),
+            failed: vec![],
+        }
+    }
+
+    pub fn on_progress(&mut self, progress: Progress) {
+        match progress {
+            Progress::Started { total } => {
+                self.total = total;
+                self.started = Instant::now();
+            }
+            Progress::Queued { .. } => {}
+            Progress::Fetching { project, dir } => {
+                let active = Active {
+                    stage: "fetching",
+                    started: Instant::now(),
+                    dir: Some(dir.to_string()),
+                    size: 0,
+                };
+                self.active
+                    .insert(project.path_with_namespace.clone(), active);
+            }
+            Progress::Pushing { project } => {
+                if let Some(active) = self.active.get_mut(&project.path_with_namespace) {
+                    active.stage = "pushing";
+                    active.dir = None;
+                }
+            }
+            Progress::Cloned { project } | Progress::Skipped { project } => {
+                self.done(&project.path_with_namespace)
+            }
+            Progress::Failed { project, error } => {
+                self.done(&project.path_with_namespace);
+                let error = error.lines().next().unwrap_or_default();
+                self.failed
+                    .push(format!("{}: {}", project.path_with_namespace, error));
+            }
+            Progress::Pruned { path, backup } => {
+                let kind = if backup { "backup project" } else { "local" };
+                push_log(format!("pruned {} {}", kind, path));
+            }
+        }
+    }
+
+    fn done(&mut self, path: &str) {
+        self.done += 1;
+        if let Some(active) = self.active.remove(path) {
+            self.received += active.size;
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, stopping: bool) {
+        let [header, table, failed, logs] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(7),
+            Constraint::Length(12),
+        ])
+        .areas(frame.area());
+
+        let elapsed = self.started.elapsed();
+        let received = self.received + self.active.values().map(|a| a.size).sum::<u64>();
+        let secs = elapsed.as_secs_f64().max(1.0);
+        let queued = self.total.saturating_sub(self.done + self.active.len());
+        let label = format!(
+            "{}/{} done, {} in progress, {} queued, {} failed | {:.1} projects/min, {}/s | {}",
+            self.done,
+            self.total,
+            self.active.len(),
+            queued,
+            self.failed.len(),
+            self.done as f64 * 60.0 / secs,
+            HumanBytes((received as f64 / secs) as u64),
+            format_duration(elapsed),
+        );
+        let ratio = match self.total {
+            0 => 0.0,
+            total => self.done as f64 / total as f64,
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title(match stopping {
+                    true => " gitlobster (stopping) ",
+                    false => " gitlobster ",
+                }))
+                .gauge_style(Style::new().green())
+                .ratio(ratio.min(1.0))
+                .label(label),
+            header,
+        );
+
+        let rows = self.active.iter().map(|(path, active)| {
+            Row::new([
+                path.clone(),
+                active.stage.to_string(),
+                HumanBytes(active.size).to_string(),
+                format_duration(active.started.elapsed()),
+            ])
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Min(30),
+                    Constraint::Length(10),
+                    Constraint::Length(12),
+                    Constraint::Length(10),
+                ],
+            )
+            .header(Row::new(["Project", "Stage", "Received", "Elapsed"]).bold())
+            .block(Block::bordered().title(" In progress ")),
+            table,
+        );
+
+        let lines = |items: Vec<&String>, height: u16| -> Vec<Line> {
+            let skip = items
+                .len()
+                .saturating_sub(height.saturating_sub(2) as usize);
+            items
+                .into_iter()
+                .skip(skip)
+                .map(|l| Line::raw(l.clone()))
+                .collect()
+        };
+        frame.render_widget(
+            Paragraph::new(lines(self.failed.iter().collect(), failed.height))
+                .red()
+                .block(Block::bordered().title(format!(" Failed ({}) ", self.failed.len()))),
+            failed,
+        );
+        let log = LOGS.lock().unwrap();
+        frame.render_widget(
+            Paragraph::new(lines(log.iter().collect(), logs.height))
+                .block(Block::bordered().title(" Log ")),
+            logs,
+        );
+    }
+
+    /// Updates received sizes of fetched projects, sizes are counted without the lock,
+    /// so they don't block progress of the run
+    fn update_sizes(dashboard: &Mutex<Dashboard>) {
+        let fetching: Vec<(String, String)> = dashboard
+            .lock()
+            .unwrap()
+            .active
+            .iter()
+            .filter_map(|(path, a)| Some((path.clone(), a.dir.clone()?)))
+            .collect();
+        for (path, dir) in fetching {
+            let size = cloner::dir_size(Path::new(&dir));
+            let mut dashboard = dashboard.lock().unwrap();
+            if let Some(active) = dashboard.active.get_mut(&path) {
+                if active.dir.is_some() {
+                    active.size = size;
+                }
+            }
+        }
+    }
+}
+
+/// `h:mm:ss` or `m:ss`
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+/// The dashboard shown in the alternate screen of the terminal until it's stopped
+pub struct Tui {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+}
+
+impl Tui {
+    /// `q` or `Ctrl+C` interrupt the run like signals
+    pub fn start(dashboard: Arc<Mutex<Dashboard>>, shutdown: Arc<Shutdown>) -> Result<Self> {
+        enable_raw_mode().context("Failed to set up the terminal")?;
+        execute!(io::stdout(), EnterAlternateScreen).context("Failed to set up the terminal")?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.clear()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread =
+            std::thread::spawn(move || draw_loop(&mut terminal, &dashboard, &shutdown, &stopped));
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Restores the terminal, the run goes on printing to it
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        restore_terminal();
+    }
+}
+
+fn draw_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    dashboard: &Mutex<Dashboard>,
+    shutdown: &Shutdown,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        Dashboard::update_sizes(dashboard);
+        let stopping = shutdown.is_stopping();
+        terminal.draw(|frame| dashboard.lock().unwrap().draw(frame, stopping))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        // the terminal is in the raw mode, so interrupts come as keys
+        if let Event::Key(key) = event::read()? {
+            let interrupt =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press && (interrupt || key.code == KeyCode::Char('q')) {
+                cli::interrupt(shutdown, true);
+            }
+        }
+    }
+    Ok(())
+}
@@ -114,7 +114,7 @@ mod tests {
 
     fn run_gitlobster(gitlab_token: &str, enable_ssh: bool) -> ExitStatus {
         let mut cmd = format!(
-            "cargo run -- \
+            "cargo run -- backup \
             --ft={} \
             --fu={} \
             --bt={} \